@@ -23,5 +23,7 @@ pub use self::legacy::ConnectionKillError as LegacyConnectionKillError;
 
 mod group;
 mod legacy;
+#[cfg(test)]
+mod memory;
 mod notif_in;
 mod notif_out;
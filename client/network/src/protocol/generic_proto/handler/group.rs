@@ -48,7 +48,10 @@
 use crate::protocol::generic_proto::{
 	handler::legacy::{LegacyProtoHandler, LegacyProtoHandlerProto, LegacyProtoHandlerIn, LegacyProtoHandlerOut},
 	handler::notif_in::{NotifsInHandlerProto, NotifsInHandler, NotifsInHandlerIn, NotifsInHandlerOut},
-	handler::notif_out::{NotifsOutHandlerProto, NotifsOutHandler, NotifsOutHandlerIn, NotifsOutHandlerOut},
+	handler::notif_out::{
+		ConnectionUniqueId, NotifsOutHandlerProto, NotifsOutHandler, NotifsOutHandlerIn,
+		NotifsOutHandlerOut, NotifsOutOpenInfo,
+	},
 	upgrade::{NotificationsIn, NotificationsOut, NotificationsHandshakeError, RegisteredProtocol, UpgradeCollec},
 };
 
@@ -162,6 +165,8 @@ impl IntoProtocolsHandler for NotifsHandlerProto {
 	}
 
 	fn into_handler(self, remote_peer_id: &PeerId, connected_point: &ConnectedPoint) -> Self::Handler {
+		// The outbound handlers of all the protocols report the same connection.
+		let connection_id = ConnectionUniqueId::allocate();
 		NotifsHandler {
 			in_handlers: self.in_handlers
 				.into_iter()
@@ -169,7 +174,10 @@ impl IntoProtocolsHandler for NotifsHandlerProto {
 				.collect(),
 			out_handlers: self.out_handlers
 				.into_iter()
-				.map(|(proto, msg)| (proto.into_handler(remote_peer_id, connected_point), msg))
+				.map(|(proto, msg)| {
+					let proto = proto.with_connection_id(connection_id);
+					(proto.into_handler(remote_peer_id, connected_point), msg)
+				})
 				.collect(),
 			endpoint: connected_point.clone(),
 			legacy: self.legacy.into_handler(remote_peer_id, connected_point),
@@ -402,8 +410,8 @@ impl ProtocolsHandler for NotifsHandler {
 	type Error = NotifsHandlerError;
 	type InboundProtocol = SelectUpgrade<UpgradeCollec<NotificationsIn>, RegisteredProtocol>;
 	type OutboundProtocol = EitherUpgrade<NotificationsOut, RegisteredProtocol>;
-	// Index within the `out_handlers`; None for legacy
-	type OutboundOpenInfo = Option<usize>;
+	// Index within the `out_handlers` and information of the handler; None for legacy
+	type OutboundOpenInfo = Option<(usize, NotifsOutOpenInfo)>;
 	type InboundOpenInfo = ();
 
 	fn listen_protocol(&self) -> SubstreamProtocol<Self::InboundProtocol, ()> {
@@ -434,8 +442,8 @@ impl ProtocolsHandler for NotifsHandler {
 		num: Self::OutboundOpenInfo
 	) {
 		match (out, num) {
-			(EitherOutput::First(out), Some(num)) =>
-				self.out_handlers[num].0.inject_fully_negotiated_outbound(out, ()),
+			(EitherOutput::First(out), Some((num, info))) =>
+				self.out_handlers[num].0.inject_fully_negotiated_outbound(out, info),
 			(EitherOutput::Second(out), None) =>
 				self.legacy.inject_fully_negotiated_outbound(out, ()),
 			_ => error!("inject_fully_negotiated_outbound called with wrong parameters"),
@@ -488,27 +496,27 @@ impl ProtocolsHandler for NotifsHandler {
 
 	fn inject_dial_upgrade_error(
 		&mut self,
-		num: Option<usize>,
+		num: Self::OutboundOpenInfo,
 		err: ProtocolsHandlerUpgrErr<EitherError<NotificationsHandshakeError, io::Error>>
 	) {
 		match (err, num) {
-			(ProtocolsHandlerUpgrErr::Timeout, Some(num)) =>
+			(ProtocolsHandlerUpgrErr::Timeout, Some((num, info))) =>
 				self.out_handlers[num].0.inject_dial_upgrade_error(
-					(),
+					info,
 					ProtocolsHandlerUpgrErr::Timeout
 				),
 			(ProtocolsHandlerUpgrErr::Timeout, None) =>
 				self.legacy.inject_dial_upgrade_error((), ProtocolsHandlerUpgrErr::Timeout),
-			(ProtocolsHandlerUpgrErr::Timer, Some(num)) =>
+			(ProtocolsHandlerUpgrErr::Timer, Some((num, info))) =>
 				self.out_handlers[num].0.inject_dial_upgrade_error(
-					(),
+					info,
 					ProtocolsHandlerUpgrErr::Timer
 				),
 			(ProtocolsHandlerUpgrErr::Timer, None) =>
 				self.legacy.inject_dial_upgrade_error((), ProtocolsHandlerUpgrErr::Timer),
-			(ProtocolsHandlerUpgrErr::Upgrade(UpgradeError::Select(err)), Some(num)) =>
+			(ProtocolsHandlerUpgrErr::Upgrade(UpgradeError::Select(err)), Some((num, info))) =>
 				self.out_handlers[num].0.inject_dial_upgrade_error(
-					(),
+					info,
 					ProtocolsHandlerUpgrErr::Upgrade(UpgradeError::Select(err))
 				),
 			(ProtocolsHandlerUpgrErr::Upgrade(UpgradeError::Select(err)), None) =>
@@ -516,9 +524,9 @@ impl ProtocolsHandler for NotifsHandler {
 					(),
 					ProtocolsHandlerUpgrErr::Upgrade(UpgradeError::Select(err))
 				),
-			(ProtocolsHandlerUpgrErr::Upgrade(UpgradeError::Apply(EitherError::A(err))), Some(num)) =>
+			(ProtocolsHandlerUpgrErr::Upgrade(UpgradeError::Apply(EitherError::A(err))), Some((num, info))) =>
 				self.out_handlers[num].0.inject_dial_upgrade_error(
-					(),
+					info,
 					ProtocolsHandlerUpgrErr::Upgrade(UpgradeError::Apply(err))
 				),
 			(ProtocolsHandlerUpgrErr::Upgrade(UpgradeError::Apply(EitherError::B(err))), None) =>
@@ -724,12 +732,12 @@ impl ProtocolsHandler for NotifsHandler {
 						return Poll::Ready(ProtocolsHandlerEvent::OutboundSubstreamRequest {
 							protocol: protocol
 								.map_upgrade(EitherUpgrade::A)
-								.map_info(|()| Some(handler_num))
+								.map_info(|info| Some((handler_num, info)))
 						}),
 					ProtocolsHandlerEvent::Close(err) => void::unreachable(err),
 
 					// Opened substream on the handshake-bearing notification protocol.
-					ProtocolsHandlerEvent::Custom(NotifsOutHandlerOut::Open { handshake })
+					ProtocolsHandlerEvent::Custom(NotifsOutHandlerOut::Open { handshake, .. })
 						if handler_num == 0 =>
 					{
 						if self.notifications_sink_rx.is_none() && self.pending_handshake.is_none() {
@@ -740,8 +748,12 @@ impl ProtocolsHandler for NotifsHandler {
 					// Nothing to do in response to other notification substreams being opened
 					// or closed.
 					ProtocolsHandlerEvent::Custom(NotifsOutHandlerOut::Open { .. }) => {},
-					ProtocolsHandlerEvent::Custom(NotifsOutHandlerOut::Closed) => {},
-					ProtocolsHandlerEvent::Custom(NotifsOutHandlerOut::Refused) => {},
+					ProtocolsHandlerEvent::Custom(NotifsOutHandlerOut::Closed { .. }) => {},
+					ProtocolsHandlerEvent::Custom(NotifsOutHandlerOut::Refused { .. }) => {},
+					ProtocolsHandlerEvent::Custom(NotifsOutHandlerOut::Disabled { .. }) => {},
+
+					// Notifications discarded by `send_or_discard` need no further handling.
+					ProtocolsHandlerEvent::Custom(NotifsOutHandlerOut::SendDropped { .. }) => {},
 				}
 			}
 		}
@@ -0,0 +1,111 @@
+// This file is part of Substrate.
+
+// Copyright (C) 2020 Parity Technologies (UK) Ltd.
+// SPDX-License-Identifier: GPL-3.0-or-later WITH Classpath-exception-2.0
+
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the
+// GNU General Public License for more details.
+
+// You should have received a copy of the GNU General Public License
+// along with this program. If not, see <https://www.gnu.org/licenses/>.
+
+//! In-memory connections, on which the handlers are tested against actual substreams.
+
+use futures::prelude::*;
+use libp2p::core::{
+	InboundUpgrade, Multiaddr, OutboundUpgrade, Transport,
+	muxing::{self, StreamMuxer, StreamMuxerBox},
+	transport::{ListenerEvent, MemoryTransport},
+	upgrade,
+};
+use libp2p::mplex::MplexConfig;
+use libp2p::swarm::NegotiatedSubstream;
+use std::{io, sync::Arc};
+
+/// Connection between two nodes over the memory transport, multiplexed with mplex.
+///
+/// The node that dialed the connection is the one whose handler is being tested, and the node
+/// that accepted it plays the remote.
+pub struct Connection {
+	dialer: Arc<StreamMuxerBox>,
+	listener: Arc<StreamMuxerBox>,
+}
+
+impl Connection {
+	/// Establishes a new connection.
+	pub fn new() -> Self {
+		let addr: Multiaddr = format!("/memory/{}", rand::random::<u64>()).parse().unwrap();
+		let mut listener = MemoryTransport.listen_on(addr.clone()).unwrap();
+
+		async_std::task::block_on(async move {
+			let dialer = MemoryTransport.dial(addr).unwrap();
+			let listener = async move {
+				loop {
+					if let ListenerEvent::Upgrade { upgrade, .. } =
+						listener.next().await.unwrap().unwrap()
+					{
+						break upgrade.await;
+					}
+				}
+			};
+			let (dialer, listener) = future::join(dialer, listener).await;
+
+			// The multiplexer is applied directly, without negotiating it first.
+			let info = b"/mplex/6.7.0";
+			let (dialer, listener) = future::join(
+				MplexConfig::new().upgrade_outbound(dialer.unwrap(), info),
+				MplexConfig::new().upgrade_inbound(listener.unwrap(), info),
+			).await;
+
+			Connection {
+				dialer: Arc::new(StreamMuxerBox::new(dialer.unwrap())),
+				listener: Arc::new(StreamMuxerBox::new(listener.unwrap())),
+			}
+		})
+	}
+
+	/// Opens a new substream on which `protocol_name` is negotiated, and returns the side of the
+	/// dialer and the side of the listener.
+	pub fn substream(&self, protocol_name: &[u8]) -> (NegotiatedSubstream, NegotiatedSubstream) {
+		let negotiated = |substream, _| future::ok::<_, io::Error>(substream);
+
+		let dialer = self.dialer.clone();
+		let name = protocol_name.to_vec();
+		let outbound = async move {
+			let substream = muxing::outbound_from_ref_and_wrap(dialer).await.unwrap();
+			let upgrade = upgrade::from_fn(name, negotiated);
+			upgrade::apply_outbound(substream, upgrade, upgrade::Version::V1).await.unwrap()
+		};
+
+		let listener = self.listener.clone();
+		let name = protocol_name.to_vec();
+		let inbound = async move {
+			let substream = loop {
+				let event = muxing::event_from_ref_and_wrap(listener.clone()).await.unwrap();
+				if let Some(substream) = event.into_inbound_substream() {
+					break substream;
+				}
+			};
+			let upgrade = upgrade::from_fn(name, negotiated);
+			let mut substream = upgrade::apply_inbound(substream, upgrade).await.unwrap();
+			// The confirmation of the protocol is only sent with the first data otherwise.
+			substream.flush().await.unwrap();
+			substream
+		};
+
+		async_std::task::block_on(future::join(outbound, inbound))
+	}
+
+	/// Closes the connection on the side of the dialer, after which all its substreams fail.
+	pub fn close(&self) {
+		async_std::task::block_on(future::poll_fn(|cx| self.dialer.close(cx))).unwrap();
+	}
+}
+
@@ -291,3 +291,162 @@ impl fmt::Debug for NotifsInHandler {
 			.finish()
 	}
 }
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use super::super::memory::Connection;
+	use crate::protocol::generic_proto::upgrade::{NotificationsOut, NotificationsOutSubstream};
+	use futures::{executor::block_on, task::noop_waker_ref};
+	use libp2p::core::{Multiaddr, OutboundUpgrade};
+
+	const PROTOCOL: &str = "/test/proto/1";
+
+	/// Side of the remote of a substream that the handler hasn't accepted yet.
+	type Opening = <NotificationsOut as OutboundUpgrade<NegotiatedSubstream>>::Future;
+
+	fn poll(
+		handler: &mut NotifsInHandler
+	) -> Poll<ProtocolsHandlerEvent<DeniedUpgrade, (), NotifsInHandlerOut, void::Void>> {
+		handler.poll(&mut Context::from_waker(noop_waker_ref()))
+	}
+
+	/// Makes the remote open a substream with the given initial message, and reports it to the
+	/// handler once the initial message has been received.
+	fn open(
+		handler: &mut NotifsInHandler,
+		connection: &Connection,
+		initial_message: &[u8]
+	) -> Opening {
+		let name = Cow::Borrowed(PROTOCOL.as_bytes());
+		let (dialer, listener) = connection.substream(&name);
+		let outbound = NotificationsOut::new(PROTOCOL, initial_message)
+			.upgrade_outbound(dialer, name.clone());
+		let inbound = NotificationsIn::new(PROTOCOL).upgrade_inbound(listener, name);
+
+		match block_on(future::select(inbound, outbound)) {
+			future::Either::Left((Ok(inbound), outbound)) => {
+				handler.inject_fully_negotiated_inbound(inbound, ());
+				outbound
+			},
+			_ => panic!("the initial message should have been received"),
+		}
+	}
+
+	/// Makes the remote open a substream with the given initial message, and accepts it.
+	fn accept(
+		handler: &mut NotifsInHandler,
+		connection: &Connection,
+		initial_message: &[u8]
+	) -> NotificationsOutSubstream<NegotiatedSubstream> {
+		let outbound = open(handler, connection, initial_message);
+		assert!(matches!(
+			poll(handler),
+			Poll::Ready(ProtocolsHandlerEvent::Custom(NotifsInHandlerOut::OpenRequest(_)))
+		));
+		handler.inject_event(NotifsInHandlerIn::Accept(b"handshake".to_vec()));
+		assert!(poll(handler).is_pending());
+		let (handshake, substream) = block_on(outbound).unwrap();
+		assert_eq!(handshake, b"handshake");
+		substream
+	}
+
+	fn handler(proto: NotifsInHandlerProto) -> NotifsInHandler {
+		let endpoint = ConnectedPoint::Dialer { address: Multiaddr::empty() };
+		proto.into_handler(&PeerId::random(), &endpoint)
+	}
+
+	#[test]
+	fn inbound_substream_is_reported_and_accepted() {
+		let mut handler = handler(NotifsInHandlerProto::new(PROTOCOL));
+		assert_eq!(handler.connection_keep_alive(), KeepAlive::No);
+
+		let connection = Connection::new();
+		let mut outbound = open(&mut handler, &connection, b"hello");
+		assert!(matches!(
+			poll(&mut handler),
+			Poll::Ready(ProtocolsHandlerEvent::Custom(NotifsInHandlerOut::OpenRequest(msg)))
+				if msg == b"hello"
+		));
+		assert_eq!(handler.connection_keep_alive(), KeepAlive::Yes);
+
+		// The handshake isn't sent before the substream is accepted.
+		assert!(poll(&mut handler).is_pending());
+		assert!(outbound.poll_unpin(&mut Context::from_waker(noop_waker_ref())).is_pending());
+
+		handler.inject_event(NotifsInHandlerIn::Accept(b"handshake".to_vec()));
+		assert!(poll(&mut handler).is_pending());
+		let (handshake, mut substream) = block_on(outbound).unwrap();
+		assert_eq!(handshake, b"handshake");
+
+		block_on(substream.send(b"notif".to_vec())).unwrap();
+		assert!(matches!(
+			poll(&mut handler),
+			Poll::Ready(ProtocolsHandlerEvent::Custom(NotifsInHandlerOut::Notif(msg)))
+				if &msg[..] == b"notif"
+		));
+		assert!(poll(&mut handler).is_pending());
+	}
+
+	#[test]
+	fn refused_substream_is_dropped() {
+		let mut handler = handler(NotifsInHandlerProto::new(PROTOCOL));
+		let connection = Connection::new();
+		let _outbound = open(&mut handler, &connection, b"hello");
+		assert!(poll(&mut handler).is_ready());
+
+		handler.inject_event(NotifsInHandlerIn::Refuse);
+		assert!(poll(&mut handler).is_pending());
+		assert_eq!(handler.connection_keep_alive(), KeepAlive::No);
+	}
+
+	#[test]
+	fn new_inbound_substream_replaces_the_existing_one() {
+		let mut handler = handler(NotifsInHandlerProto::new(PROTOCOL));
+		let connection = Connection::new();
+		let mut first = accept(&mut handler, &connection, b"first");
+
+		let second = open(&mut handler, &connection, b"second");
+		assert!(matches!(
+			poll(&mut handler),
+			Poll::Ready(ProtocolsHandlerEvent::Custom(NotifsInHandlerOut::Closed))
+		));
+		assert!(matches!(
+			poll(&mut handler),
+			Poll::Ready(ProtocolsHandlerEvent::Custom(NotifsInHandlerOut::OpenRequest(msg)))
+				if msg == b"second"
+		));
+		handler.inject_event(NotifsInHandlerIn::Accept(b"handshake".to_vec()));
+		assert!(poll(&mut handler).is_pending());
+		let (_, mut second) = block_on(second).unwrap();
+
+		// Only the notifications of the new substream are received.
+		block_on(first.send(b"old".to_vec())).unwrap();
+		block_on(second.send(b"new".to_vec())).unwrap();
+		assert!(matches!(
+			poll(&mut handler),
+			Poll::Ready(ProtocolsHandlerEvent::Custom(NotifsInHandlerOut::Notif(msg)))
+				if &msg[..] == b"new"
+		));
+		assert!(poll(&mut handler).is_pending());
+	}
+
+	#[test]
+	fn remote_closing_the_substream_is_reported() {
+		let mut handler = handler(NotifsInHandlerProto::new(PROTOCOL));
+		let connection = Connection::new();
+		let mut substream = accept(&mut handler, &connection, b"hello");
+
+		block_on(substream.send(b"notif".to_vec())).unwrap();
+		block_on(substream.close()).unwrap();
+		assert!(matches!(
+			poll(&mut handler),
+			Poll::Ready(ProtocolsHandlerEvent::Custom(NotifsInHandlerOut::Notif(_)))
+		));
+		assert!(matches!(
+			poll(&mut handler),
+			Poll::Ready(ProtocolsHandlerEvent::Custom(NotifsInHandlerOut::Closed))
+		));
+		assert_eq!(handler.connection_keep_alive(), KeepAlive::No);
+	}
+}
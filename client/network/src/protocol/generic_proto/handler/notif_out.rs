@@ -21,10 +21,13 @@
 //! >			protocols, you need to create multiple instances and group them.
 //!
 
-use crate::protocol::generic_proto::upgrade::{NotificationsOut, NotificationsOutSubstream, NotificationsHandshakeError};
+use crate::protocol::generic_proto::upgrade::{
+	NotificationsOut, NotificationsOutSubstream, NotificationsHandshakeError,
+};
 use futures::prelude::*;
+use futures_timer::Delay;
 use libp2p::core::{ConnectedPoint, PeerId};
-use libp2p::core::upgrade::{DeniedUpgrade, InboundUpgrade, OutboundUpgrade};
+use libp2p::core::upgrade::{DeniedUpgrade, InboundUpgrade, OutboundUpgrade, UpgradeError};
 use libp2p::swarm::{
 	ProtocolsHandler, ProtocolsHandlerEvent,
 	IntoProtocolsHandler,
@@ -35,18 +38,32 @@ use libp2p::swarm::{
 };
 use log::{debug, warn, error};
 use std::{
-	borrow::Cow, collections::VecDeque, fmt, mem, pin::Pin, task::{Context, Poll, Waker},
+	borrow::Cow, collections::VecDeque, fmt, mem, pin::Pin,
+	sync::{Arc, atomic::{AtomicBool, AtomicU64, Ordering}},
+	task::{Context, Poll, Waker},
 	time::Duration
 };
 use wasm_timer::Instant;
 
-/// Maximum duration to open a substream and receive the handshake message. After that, we
-/// consider that we failed to open the substream.
-const OPEN_TIMEOUT: Duration = Duration::from_secs(10);
+/// Maximum duration to negotiate a substream with the remote. After that, we consider that we
+/// failed to open the substream.
+const CONNECT_TIMEOUT: Duration = Duration::from_secs(5);
+/// Maximum duration to receive the handshake message of the remote once the substream has been
+/// negotiated. After that, we consider that we failed to open the substream.
+const HANDSHAKE_TIMEOUT: Duration = Duration::from_secs(5);
 /// After successfully establishing a connection with the remote, we keep the connection open for
 /// at least this amount of time in order to give the rest of the code the chance to notify us to
 /// open substreams.
 const INITIAL_KEEPALIVE_TIME: Duration = Duration::from_secs(5);
+/// Minimum duration between two successive attempts to re-open a substream after it
+/// has failed.
+const MIN_REOPEN_INTERVAL: Duration = Duration::from_secs(1);
+/// Additional duration, on top of the timeout of a substream request, after which we stop
+/// waiting for libp2p to report the outcome of the request.
+const OPEN_WATCHDOG_SLACK: Duration = Duration::from_secs(5);
+
+/// Identifier to assign to the next connection for which a [`NotifsOutHandler`] is created.
+static NEXT_CONNECTION_ID: AtomicU64 = AtomicU64::new(0);
 
 /// Implements the `IntoProtocolsHandler` trait of libp2p.
 ///
@@ -58,16 +75,120 @@ const INITIAL_KEEPALIVE_TIME: Duration = Duration::from_secs(5);
 pub struct NotifsOutHandlerProto {
 	/// Name of the protocol to negotiate.
 	protocol_name: Cow<'static, str>,
+	/// Source of the current time.
+	clock: Arc<dyn Clock>,
+	/// Identifier of the connection the handler is created for. Allocated when the handler is
+	/// created if `None`.
+	connection_id: Option<ConnectionUniqueId>,
+}
+
+/// Source of the current time used by a [`NotifsOutHandler`].
+///
+/// Both the timestamps, such as the opening time of the connection or the keep-alive deadlines,
+/// and the timers, such as the back-off after a refusal, go through the clock.
+pub trait Clock: Send + Sync {
+	/// Returns the current time.
+	fn now(&self) -> Instant;
+
+	/// Returns a timer that fires once `duration` has elapsed according to [`Clock::now`].
+	fn delay(&self, duration: Duration) -> ClockDelay;
+}
+
+/// Timer returned by [`Clock::delay`].
+pub type ClockDelay = Pin<Box<dyn Future<Output = ()> + Send>>;
+
+/// [`Clock`] that returns the actual time. This is the default.
+#[derive(Debug, Default, Clone, Copy)]
+pub struct SystemClock;
+
+impl Clock for SystemClock {
+	fn now(&self) -> Instant {
+		Instant::now()
+	}
+
+	fn delay(&self, duration: Duration) -> ClockDelay {
+		Box::pin(Delay::new(duration))
+	}
 }
 
+/// Reason why a [`NotifsOutHandler`] has discarded a notification.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum DropReason {
+	/// The substream wasn't open, or has been closed before the notification could be written
+	/// to it.
+	NotOpen,
+}
+
+/// Error returned by [`NotifsOutHandlerProto::try_new`] if the protocol name isn't a valid
+/// multistream-select protocol name.
+#[derive(Debug, derive_more::Display)]
+#[display(fmt = "Invalid notifications protocol name {:?}: must be non-empty and start with /", _0)]
+pub struct InvalidProtocolName(pub Cow<'static, str>);
+
+impl std::error::Error for InvalidProtocolName {}
+
 impl NotifsOutHandlerProto {
 	/// Builds a new [`NotifsOutHandlerProto`]. Will use the given protocol name for the
 	/// notifications substream.
+	///
+	/// The protocol name must be non-empty and start with `/`. An invalid name is reported as an
+	/// error, and panics in debug builds. See also [`NotifsOutHandlerProto::try_new`].
 	pub fn new(protocol_name: impl Into<Cow<'static, str>>) -> Self {
+		match NotifsOutHandlerProto::try_new(protocol_name) {
+			Ok(proto) => proto,
+			Err(InvalidProtocolName(protocol_name)) => {
+				error!(
+					target: "sub-libp2p",
+					"📞 Invalid notifications protocol name: {:?}",
+					protocol_name,
+				);
+				debug_assert!(false, "Invalid notifications protocol name: {:?}", protocol_name);
+				NotifsOutHandlerProto::new_unchecked(protocol_name)
+			}
+		}
+	}
+
+	/// Builds a new [`NotifsOutHandlerProto`], or returns an error if the protocol name is
+	/// empty or doesn't start with `/`.
+	pub fn try_new(
+		protocol_name: impl Into<Cow<'static, str>>
+	) -> Result<Self, InvalidProtocolName> {
+		let protocol_name = protocol_name.into();
+		if !protocol_name.starts_with('/') {
+			return Err(InvalidProtocolName(protocol_name));
+		}
+
+		Ok(NotifsOutHandlerProto::new_unchecked(protocol_name))
+	}
+
+	/// Builds a new [`NotifsOutHandlerProto`] without validating the protocol name.
+	fn new_unchecked(protocol_name: Cow<'static, str>) -> Self {
 		NotifsOutHandlerProto {
-			protocol_name: protocol_name.into(),
+			protocol_name,
+			clock: Arc::new(SystemClock),
+			connection_id: None,
 		}
 	}
+
+	/// Uses `id` as the identifier of the connection, found in the events of the handler, instead
+	/// of allocating a new one.
+	///
+	/// When a connection has a handler for each of several protocols, they must all be given the
+	/// same identifier in order for their events to be attributed to this connection.
+	pub fn with_connection_id(mut self, id: ConnectionUniqueId) -> Self {
+		self.connection_id = Some(id);
+		self
+	}
+}
+
+#[cfg(test)]
+impl NotifsOutHandlerProto {
+	/// Uses `clock` as the source of the current time and of the timers, instead of the
+	/// [`SystemClock`], in order to control the passing of time.
+	pub fn with_clock(mut self, clock: Arc<dyn Clock>) -> Self {
+		self.clock = clock;
+		self
+	}
 }
 
 impl IntoProtocolsHandler for NotifsOutHandlerProto {
@@ -77,12 +198,39 @@ impl IntoProtocolsHandler for NotifsOutHandlerProto {
 		DeniedUpgrade
 	}
 
-	fn into_handler(self, _: &PeerId, _: &ConnectedPoint) -> Self::Handler {
+	fn into_handler(self, peer_id: &PeerId, _: &ConnectedPoint) -> Self::Handler {
+		self.build_for(peer_id.clone())
+	}
+}
+
+impl NotifsOutHandlerProto {
+	/// Turns the prototype into a handler, for a connection with a random remote.
+	#[cfg(test)]
+	fn build(self) -> NotifsOutHandler {
+		self.build_for(PeerId::random())
+	}
+
+	/// Turns the prototype into a handler, for a connection with `peer_id`.
+	fn build_for(self, peer_id: PeerId) -> NotifsOutHandler {
+		let now = self.clock.now();
 		NotifsOutHandler {
+			peer_id,
+			retired: Vec::new(),
+			id: self.connection_id.unwrap_or_else(ConnectionUniqueId::allocate),
 			protocol_name: self.protocol_name,
-			when_connection_open: Instant::now(),
+			clock: self.clock,
+			when_connection_open: now,
 			state: State::Disabled,
+			outbound_requests: 0,
+			open_watchdog: None,
+			connect_timers: VecDeque::new(),
+			abandoned_requests: Vec::new(),
 			events_queue: VecDeque::new(),
+			lifecycle_events: VecDeque::new(),
+			last_reopen: None,
+			reopens: 0,
+			reopen_timer: None,
+			disabled_reported: true,
 		}
 	}
 }
@@ -96,20 +244,81 @@ impl IntoProtocolsHandler for NotifsOutHandlerProto {
 /// handler. Once done, the handler will try to establish then maintain an outbound substream with
 /// the remote for the purpose of sending notifications to it.
 pub struct NotifsOutHandler {
+	/// Identifier of this handler.
+	id: ConnectionUniqueId,
+
+	/// Identity of the remote.
+	peer_id: PeerId,
+
 	/// Name of the protocol to negotiate.
 	protocol_name: Cow<'static, str>,
 
+	/// Source of the current time.
+	clock: Arc<dyn Clock>,
+
 	/// Relationship with the node we're connected to.
 	state: State,
 
+	/// Number of outbound substreams that have been requested but not negotiated yet.
+	///
+	/// Since we always request substreams with the same initial message, they are
+	/// interchangeable, and a negotiated substream is used for whatever substream is needed at
+	/// the time it arrives.
+	outbound_requests: usize,
+
+	/// If the main substream is being opened, fires once we stop waiting for the outcome of the
+	/// latest request.
+	open_watchdog: Option<ClockDelay>,
+
+	/// Connect timeouts of the substream requests whose outcome hasn't been reported yet, in the
+	/// order of the requests, alongside the flag that their upgrade sets once they have been
+	/// negotiated.
+	connect_timers: VecDeque<(ClockDelay, Arc<AtomicBool>)>,
+
+	/// Flags of the substream requests that have been given up on at the connect timeout. The
+	/// outcome that libp2p reports for them later is ignored.
+	abandoned_requests: Vec<Arc<AtomicBool>>,
+
 	/// When the connection with the remote has been successfully established.
 	when_connection_open: Instant,
 
+	/// True if [`NotifsOutHandlerOut::Disabled`] has been emitted since the handler last entered
+	/// `State::Disabled`. Starts as `true`, as the handler is created disabled.
+	disabled_reported: bool,
+
 	/// Queue of events to send to the outside.
 	///
 	/// This queue must only ever be modified to insert elements at the back, or remove the first
 	/// element.
-	events_queue: VecDeque<ProtocolsHandlerEvent<NotificationsOut, (), NotifsOutHandlerOut, void::Void>>,
+	///
+	/// This is a `VecDeque` rather than a `SmallVec`, so that handlers that never queue events
+	/// don't reserve any space for them.
+	events_queue: VecDeque<
+		ProtocolsHandlerEvent<NotificationsOut, NotifsOutOpenInfo, NotifsOutHandlerOut, void::Void>
+	>,
+
+	/// Queue of [`NotifsOutHandlerOut::Open`], [`NotifsOutHandlerOut::Closed`] and
+	/// [`NotifsOutHandlerOut::Refused`] events to send to the outside.
+	///
+	/// These events report changes in the state of the handler, and are always sent out before
+	/// the ones in `events_queue`, so that the outside can react to them promptly. The same
+	/// rules as for `events_queue` apply.
+	lifecycle_events: VecDeque<NotifsOutHandlerOut>,
+
+	/// When a substream has last been requested in order to replace a substream that failed.
+	last_reopen: Option<Instant>,
+
+	/// Number of times the substream has been re-opened after failing or reaching its maximum
+	/// age.
+	reopens: u64,
+
+	/// Fires when a new substream must be requested, if re-opening the substream after a
+	/// failure is being delayed.
+	reopen_timer: Option<ClockDelay>,
+
+	/// Substreams that are being closed in the background, because they have been negotiated
+	/// after they were no longer needed.
+	retired: Vec<NotificationsOutSubstream<NegotiatedSubstream>>,
 }
 
 /// Our relationship with the node we're connected to.
@@ -123,7 +332,10 @@ enum State {
 	/// >				 but the `Sink` API is unclear about whether or not the stream can then
 	/// >				 be recovered. Because of that, we must never switch from the
 	/// >				 `DisabledOpen` state to the `Open` state while keeping the same substream.
-	DisabledOpen(NotificationsOutSubstream<NegotiatedSubstream>),
+	DisabledOpen {
+		/// Substream being closed.
+		substream: NotificationsOutSubstream<NegotiatedSubstream>,
+	},
 
 	/// The handler is disabled but we are still trying to open a substream with the remote.
 	///
@@ -157,6 +369,88 @@ enum State {
 	Poisoned,
 }
 
+impl State {
+	/// Returns the name of the state, for debugging purposes.
+	fn name(&self) -> &'static str {
+		match self {
+			State::Disabled => "Disabled",
+			State::DisabledOpen { .. } => "DisabledOpen",
+			State::DisabledOpening => "DisabledOpening",
+			State::Opening { .. } => "Opening",
+			State::Refused => "Refused",
+			State::Open { .. } => "Open",
+			State::Poisoned => "Poisoned",
+		}
+	}
+}
+
+/// Identifier of a connection, unique within the process, and shared by all the
+/// [`NotifsOutHandler`]s of this connection.
+///
+/// This can be used to attribute events to a specific connection when multiple connections to
+/// the same peer exist.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct ConnectionUniqueId(u64);
+
+impl ConnectionUniqueId {
+	/// Allocates an identifier for a new connection.
+	pub fn allocate() -> Self {
+		ConnectionUniqueId(NEXT_CONNECTION_ID.fetch_add(1, Ordering::Relaxed))
+	}
+}
+
+/// Reason why a substream has been closed.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CloseReason {
+	/// The handler has been disabled and the substream has been closed cleanly.
+	Disabled,
+	/// The substream has been closed by the remote or has encountered an error.
+	Error,
+}
+
+/// Information attached by a [`NotifsOutHandler`] to the substreams that it requests, which must
+/// be passed back to it alongside their outcome.
+#[derive(Debug, Clone)]
+pub struct NotifsOutOpenInfo {
+	/// Flag shared with the upgrade, set once the substream has been negotiated.
+	negotiated: Arc<AtomicBool>,
+	/// When the request has been reported to libp2p.
+	requested_at: Option<Instant>,
+}
+
+/// Reason why an attempt to open a substream has failed.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RefusalCause {
+	/// The substream couldn't be negotiated in time.
+	Timeout,
+	/// The substream has been negotiated, but the remote didn't send back its handshake in time.
+	HandshakeTimeout,
+	/// The remote doesn't support the protocol.
+	Unsupported,
+	/// The remote refused the substream, or sent back an invalid handshake.
+	Rejected,
+	/// Failed to open the substream for a reason unrelated to the remote.
+	Other,
+	/// Neither a substream nor an error has been reported for the request within its timeout.
+	/// This indicates a bug outside of the handler, which gave up on the request.
+	WatchdogTimeout,
+}
+
+impl RefusalCause {
+	/// Determines the cause of a failure to open a substream.
+	fn from_upgrade_error(err: &ProtocolsHandlerUpgrErr<NotificationsHandshakeError>) -> Self {
+		match err {
+			ProtocolsHandlerUpgrErr::Timeout => RefusalCause::Timeout,
+			ProtocolsHandlerUpgrErr::Timer => RefusalCause::Other,
+			ProtocolsHandlerUpgrErr::Upgrade(UpgradeError::Select(_)) => RefusalCause::Unsupported,
+			ProtocolsHandlerUpgrErr::Upgrade(UpgradeError::Apply(
+				NotificationsHandshakeError::Timeout
+			)) => RefusalCause::HandshakeTimeout,
+			ProtocolsHandlerUpgrErr::Upgrade(UpgradeError::Apply(_)) => RefusalCause::Rejected,
+		}
+	}
+}
+
 /// Event that can be received by a `NotifsOutHandler`.
 #[derive(Debug)]
 pub enum NotifsOutHandlerIn {
@@ -176,17 +470,54 @@ pub enum NotifsOutHandlerIn {
 pub enum NotifsOutHandlerOut {
 	/// The notifications substream has been accepted by the remote.
 	Open {
+		/// Identifier of the connection of the handler that emitted the event.
+		id: ConnectionUniqueId,
 		/// Handshake message sent by the remote after we opened the substream.
 		handshake: Vec<u8>,
+		/// Time between the request for the substream being reported to libp2p and the
+		/// substream being negotiated, if known.
+		rtt: Option<Duration>,
 	},
 
-	/// The notifications substream has been closed by the remote.
-	Closed,
+	/// The notifications substream has been closed, either by the remote or after the handler
+	/// has been disabled.
+	Closed {
+		/// Identifier of the connection of the handler that emitted the event.
+		id: ConnectionUniqueId,
+		/// Why the substream has been closed.
+		reason: CloseReason,
+	},
 
 	/// We tried to open a notifications substream, but the remote refused it.
 	///
-	/// Can only happen if we're in a closed state.
-	Refused,
+	/// Can only happen if we're in a closed state. The handler stays enabled, and isn't followed
+	/// with a [`NotifsOutHandlerOut::Disabled`] event.
+	Refused {
+		/// Identifier of the connection of the handler that emitted the event.
+		id: ConnectionUniqueId,
+		/// Why the substream couldn't be opened.
+		cause: RefusalCause,
+	},
+
+	/// A notification passed with [`NotifsOutHandler::send_or_discard`] has been discarded.
+	SendDropped {
+		/// Identifier of the connection of the handler that emitted the event.
+		id: ConnectionUniqueId,
+		/// Why the notification has been discarded.
+		reason: DropReason,
+	},
+
+	/// The handler has become disabled and no longer holds any substream, for example after the
+	/// substream has been closed following a [`NotifsOutHandlerIn::Disable`], or after the
+	/// substream that was being opened when the handler got disabled has been negotiated or
+	/// refused.
+	///
+	/// Emitted after the corresponding [`NotifsOutHandlerOut::Closed`], if any, and only once per
+	/// transition.
+	Disabled {
+		/// Identifier of the connection of the handler that emitted the event.
+		id: ConnectionUniqueId,
+	},
 }
 
 impl NotifsOutHandler {
@@ -195,7 +526,7 @@ impl NotifsOutHandler {
 		match &self.state {
 			State::Disabled => false,
 			State::DisabledOpening => false,
-			State::DisabledOpen(_) => true,
+			State::DisabledOpen { .. } => true,
 			State::Opening { .. } => false,
 			State::Refused => false,
 			State::Open { .. } => true,
@@ -203,6 +534,20 @@ impl NotifsOutHandler {
 		}
 	}
 
+	/// Returns `true` if the handler has been enabled and tries to maintain a substream with the
+	/// remote.
+	pub fn is_enabled(&self) -> bool {
+		match &self.state {
+			State::Disabled => false,
+			State::DisabledOpening => false,
+			State::DisabledOpen { .. } => false,
+			State::Opening { .. } => true,
+			State::Refused => true,
+			State::Open { .. } => true,
+			State::Poisoned => false,
+		}
+	}
+
 	/// Returns `true` if there has been an attempt to open the substream, but the remote refused
 	/// the substream.
 	///
@@ -211,7 +556,7 @@ impl NotifsOutHandler {
 		match &self.state {
 			State::Disabled => false,
 			State::DisabledOpening => false,
-			State::DisabledOpen(_) => false,
+			State::DisabledOpen { .. } => false,
 			State::Opening { .. } => false,
 			State::Refused => true,
 			State::Open { .. } => false,
@@ -224,6 +569,30 @@ impl NotifsOutHandler {
 		&self.protocol_name
 	}
 
+	/// Returns the kinds of the events that are queued and not yet returned by `poll`, in the
+	/// order in which they are going to be returned, without removing them.
+	///
+	/// Custom events are designated by the name of their variant. Events that are generated
+	/// while polling, rather than queued in advance, aren't included.
+	#[cfg(test)]
+	fn peek_events(&self) -> Vec<&'static str> {
+		let custom_kind = |event: &NotifsOutHandlerOut| match event {
+			NotifsOutHandlerOut::Open { .. } => "Open",
+			NotifsOutHandlerOut::Closed { .. } => "Closed",
+			NotifsOutHandlerOut::Refused { .. } => "Refused",
+			NotifsOutHandlerOut::SendDropped { .. } => "SendDropped",
+			NotifsOutHandlerOut::Disabled { .. } => "Disabled",
+		};
+
+		let lifecycle = self.lifecycle_events.iter().map(custom_kind);
+		let queued = self.events_queue.iter().map(|event| match event {
+			ProtocolsHandlerEvent::OutboundSubstreamRequest { .. } => "OutboundSubstreamRequest",
+			ProtocolsHandlerEvent::Custom(event) => custom_kind(event),
+			ProtocolsHandlerEvent::Close(_) => "Close",
+		});
+		lifecycle.chain(queued).collect()
+	}
+
 	/// Polls whether the outbound substream is ready to send a notification.
 	///
 	/// - Returns `Poll::Pending` if the substream is open but not ready to send a notification.
@@ -248,90 +617,158 @@ impl NotifsOutHandler {
 	/// Sends out a notification.
 	///
 	/// If the substream is closed, or not ready to send out a notification yet, then the
-	/// notification is silently discarded.
+	/// notification is discarded with a [`NotifsOutHandlerOut::SendDropped`] event.
 	///
 	/// You are encouraged to call [`NotifsOutHandler::poll_ready`] beforehand to determine
 	/// whether this will succeed. If `Poll::Ready(true)` is returned, then this method will send
 	/// out a notification.
 	pub fn send_or_discard(&mut self, notification: Vec<u8>) {
-		if let State::Open { substream, .. } = &mut self.state {
-			let _ = substream.start_send_unpin(notification);
+		let sent = match &mut self.state {
+			State::Open { substream, .. } => substream.start_send_unpin(notification).is_ok(),
+			_ => false,
+		};
+		if !sent {
+			self.drop_notification(DropReason::NotOpen);
 		}
 	}
-}
 
-impl ProtocolsHandler for NotifsOutHandler {
-	type InEvent = NotifsOutHandlerIn;
-	type OutEvent = NotifsOutHandlerOut;
-	type Error = void::Void;
-	type InboundProtocol = DeniedUpgrade;
-	type OutboundProtocol = NotificationsOut;
-	type OutboundOpenInfo = ();
-	type InboundOpenInfo = ();
+	/// Closes `substream` in the background, because it isn't needed.
+	fn retire(&mut self, substream: NotificationsOutSubstream<NegotiatedSubstream>) {
+		self.retired.push(substream);
+	}
 
-	fn listen_protocol(&self) -> SubstreamProtocol<Self::InboundProtocol, ()> {
-		SubstreamProtocol::new(DeniedUpgrade, ())
+	/// Queues a request for a new outbound substream, sending `initial_message` as part of the
+	/// handshake.
+	fn request_substream(&mut self, initial_message: Vec<u8>) {
+		self.outbound_requests += 1;
+		let negotiated = Arc::new(AtomicBool::new(false));
+		let proto = NotificationsOut::new(self.protocol_name.clone(), initial_message)
+			.with_handshake_timeout(HANDSHAKE_TIMEOUT)
+			.with_negotiated_flag(negotiated.clone());
+		// The timeout of libp2p covers both the negotiation and the handshake, which are also
+		// timed on their own: the negotiation by the handler and the handshake by the upgrade, so
+		// that the two situations can be told apart.
+		let timer = self.clock.delay(CONNECT_TIMEOUT);
+		self.connect_timers.push_back((timer, negotiated.clone()));
+		let timeout = CONNECT_TIMEOUT + HANDSHAKE_TIMEOUT;
+		self.open_watchdog = Some(self.clock.delay(timeout + OPEN_WATCHDOG_SLACK));
+		let info = NotifsOutOpenInfo { negotiated, requested_at: None };
+		self.events_queue.push_back(ProtocolsHandlerEvent::OutboundSubstreamRequest {
+			protocol: SubstreamProtocol::new(proto, info).with_timeout(timeout),
+		});
 	}
 
-	fn inject_fully_negotiated_inbound(
-		&mut self,
-		proto: <Self::InboundProtocol as InboundUpgrade<NegotiatedSubstream>>::Output,
-		(): ()
-	) {
-		// We should never reach here. `proto` is a `Void`.
-		void::unreachable(proto)
+	/// Switches to the `Opening` state after the substream has failed, and requests a new
+	/// substream.
+	///
+	/// If the previous substream has also been opened this way less than the minimum reopen
+	/// interval ago, the request is delayed until the interval has elapsed, in order to not
+	/// flap against a persistently broken connection.
+	fn reopen_after_error(&mut self, initial_message: Vec<u8>) {
+		self.reopens += 1;
+		let now = self.clock.now();
+		let wait = self.last_reopen
+			.map(|last| last + MIN_REOPEN_INTERVAL)
+			.filter(|next| *next > now)
+			.map(|next| next - now);
+
+		self.state = State::Opening { initial_message: initial_message.clone() };
+		match wait {
+			Some(wait) => {
+				debug!(
+					target: "sub-libp2p",
+					"📞 Delaying the re-opening of notifications substream on {} by {:?}",
+					self.protocol_name,
+					wait,
+				);
+				self.reopen_timer = Some(self.clock.delay(wait));
+			},
+			None => {
+				self.last_reopen = Some(now);
+				self.request_substream(initial_message);
+			},
+		}
 	}
 
-	fn inject_fully_negotiated_outbound(
+	/// Reports that a notification has been discarded with a
+	/// [`NotifsOutHandlerOut::SendDropped`] event.
+	fn drop_notification(&mut self, reason: DropReason) {
+		let ev = NotifsOutHandlerOut::SendDropped { id: self.id, reason };
+		self.events_queue.push_back(ProtocolsHandlerEvent::Custom(ev));
+	}
+
+	/// Called when a substream that we requested has been successfully negotiated.
+	fn inject_substream(
 		&mut self,
-		(handshake_msg, substream): <Self::OutboundProtocol as OutboundUpgrade<NegotiatedSubstream>>::Output,
-		_: ()
+		handshake_msg: Vec<u8>,
+		substream: NotificationsOutSubstream<NegotiatedSubstream>,
+		requested_at: Option<Instant>
 	) {
+		let expected = self.outbound_requests != 0;
+		self.outbound_requests = self.outbound_requests.saturating_sub(1);
+		let now = self.clock.now();
+		let rtt = requested_at.map(|requested_at| now - requested_at);
+
 		match mem::replace(&mut self.state, State::Poisoned) {
 			State::Opening { initial_message } => {
-				let ev = NotifsOutHandlerOut::Open { handshake: handshake_msg };
-				self.events_queue.push_back(ProtocolsHandlerEvent::Custom(ev));
+				let handshake = handshake_msg;
+				self.open_watchdog = None;
+				let ev = NotifsOutHandlerOut::Open { id: self.id, handshake, rtt };
+				self.lifecycle_events.push_back(ev);
 				self.state = State::Open { substream, initial_message, close_waker: None };
 			},
 			// If the handler was disabled while we were negotiating the protocol, immediately
-			// close it.
-			State::DisabledOpening => self.state = State::DisabledOpen(substream),
+			// close it. Since the substream has never been reported as open, it is closed in the
+			// background rather than through the `DisabledOpen` state.
+			State::DisabledOpening => {
+				self.state = State::Disabled;
+				self.close_unused(substream);
+			},
 
-			// Any other situation should never happen.
-			State::Disabled | State::Refused | State::Open { .. } | State::DisabledOpen(_) =>
-				error!("☎️ State mismatch in notifications handler: substream already open"),
+			// A substream that was requested earlier might still be negotiated after the state has
+			// changed. Any other situation should never happen.
+			st @ State::Disabled | st @ State::Refused | st @ State::Open { .. } |
+			st @ State::DisabledOpen { .. } => {
+				if !expected {
+					error!(
+						"☎️ State mismatch in notifications handler: substream already open"
+					);
+				}
+				self.state = st;
+				self.close_unused(substream);
+			},
 			State::Poisoned => error!("☎️ Notifications handler in a poisoned state"),
 		}
 	}
 
-	fn inject_event(&mut self, message: NotifsOutHandlerIn) {
+	/// Closes a substream that has been negotiated after it was no longer needed.
+	fn close_unused(&mut self, substream: NotificationsOutSubstream<NegotiatedSubstream>) {
+		debug!(
+			target: "sub-libp2p",
+			"📞 Closing unused notifications substream on {}",
+			self.protocol_name,
+		);
+		self.retire(substream);
+	}
+
+	/// Processes a message sent by the outside.
+	fn handle_event(&mut self, message: NotifsOutHandlerIn) {
 		match message {
 			NotifsOutHandlerIn::Enable { initial_message } => {
 				match mem::replace(&mut self.state, State::Poisoned) {
 					State::Disabled => {
-						let proto = NotificationsOut::new(self.protocol_name.clone(), initial_message.clone());
-						self.events_queue.push_back(ProtocolsHandlerEvent::OutboundSubstreamRequest {
-							protocol: SubstreamProtocol::new(proto, ()).with_timeout(OPEN_TIMEOUT),
-						});
+						self.request_substream(initial_message.clone());
 						self.state = State::Opening { initial_message };
 					},
 					State::DisabledOpening => self.state = State::Opening { initial_message },
-					State::DisabledOpen(mut sub) => {
+					State::DisabledOpen { substream: sub, .. } => {
 						// As documented above, in this state we have already called `poll_close`
 						// once on the substream, and it is unclear whether the substream can then
 						// be recovered. When in doubt, let's drop the existing substream and
 						// open a new one.
-						if sub.close().now_or_never().is_none() {
-							warn!(
-								target: "sub-libp2p",
-								"📞 Improperly closed outbound notifications substream"
-							);
-						}
+						close_now(sub);
 
-						let proto = NotificationsOut::new(self.protocol_name.clone(), initial_message.clone());
-						self.events_queue.push_back(ProtocolsHandlerEvent::OutboundSubstreamRequest {
-							protocol: SubstreamProtocol::new(proto, ()).with_timeout(OPEN_TIMEOUT),
-						});
+						self.request_substream(initial_message.clone());
 						self.state = State::Opening { initial_message };
 					},
 					st @ State::Opening { .. } | st @ State::Refused | st @ State::Open { .. } => {
@@ -345,18 +782,28 @@ impl ProtocolsHandler for NotifsOutHandler {
 
 			NotifsOutHandlerIn::Disable => {
 				match mem::replace(&mut self.state, State::Poisoned) {
-					st @ State::Disabled | st @ State::DisabledOpen(_) | st @ State::DisabledOpening => {
+					st @ State::Disabled | st @ State::DisabledOpen { .. } |
+					st @ State::DisabledOpening => {
 						debug!(target: "sub-libp2p",
 							"Tried to disable notifications handler that was already disabled");
 						self.state = st;
 					}
-					State::Opening { .. } => self.state = State::DisabledOpening,
-					State::Refused => self.state = State::Disabled,
+					// No substream has been requested yet if the re-opening is being delayed.
+					State::Opening { .. } if self.reopen_timer.is_some() => {
+						self.reopen_timer = None;
+						self.state = State::Disabled;
+					},
+					State::Opening { .. } => {
+						self.state = State::DisabledOpening;
+					},
+					State::Refused => {
+						self.state = State::Disabled;
+					},
 					State::Open { substream, close_waker, .. } => {
 						if let Some(close_waker) = close_waker {
 							close_waker.wake();
 						}
-						self.state = State::DisabledOpen(substream)
+						self.state = State::DisabledOpen { substream }
 					},
 					State::Poisoned => error!("☎️ Notifications handler in a poisoned state"),
 				}
@@ -364,81 +811,1080 @@ impl ProtocolsHandler for NotifsOutHandler {
 		}
 	}
 
-	fn inject_dial_upgrade_error(&mut self, _: (), _: ProtocolsHandlerUpgrErr<NotificationsHandshakeError>) {
+	/// Called when we have failed to open a substream that we requested.
+	fn handle_dial_upgrade_error(&mut self, err: ProtocolsHandlerUpgrErr<NotificationsHandshakeError>) {
+		let cause = RefusalCause::from_upgrade_error(&err);
+
+		let expected = self.outbound_requests != 0;
+		self.outbound_requests = self.outbound_requests.saturating_sub(1);
+
+		// Since substreams are interchangeable, the failure only matters if there are now fewer
+		// pending requests than substreams that we are waiting for.
+		let waiting = matches!(self.state, State::Opening { .. } | State::DisabledOpening);
+		if self.outbound_requests >= usize::from(waiting) {
+			if !expected {
+				error!("☎️ State mismatch in NotificationsOut");
+			}
+			return;
+		}
+
 		match mem::replace(&mut self.state, State::Poisoned) {
-			State::Disabled => {},
-			State::DisabledOpen(_) | State::Refused | State::Open { .. } =>
-				error!("☎️ State mismatch in NotificationsOut"),
-			State::Opening { .. } => {
-				self.state = State::Refused;
-				let ev = NotifsOutHandlerOut::Refused;
-				self.events_queue.push_back(ProtocolsHandlerEvent::Custom(ev));
+			st @ State::Disabled => self.state = st,
+			// No substream is being opened, which the check above should have caught.
+			st @ State::DisabledOpen { .. } | st @ State::Refused | st @ State::Open { .. } => {
+				error!("☎️ State mismatch in NotificationsOut");
+				self.state = st;
 			},
+			State::Opening { .. } => self.refuse(cause),
 			State::DisabledOpening => self.state = State::Disabled,
 			State::Poisoned => error!("☎️ Notifications handler in a poisoned state"),
 		}
 	}
 
-	fn connection_keep_alive(&self) -> KeepAlive {
+	/// Switches from the `Opening` state to the `Refused` state.
+	fn refuse(&mut self, cause: RefusalCause) {
+		self.open_watchdog = None;
+		self.state = State::Refused;
+		let ev = NotifsOutHandlerOut::Refused { id: self.id, cause };
+		self.lifecycle_events.push_back(ev);
+	}
+
+	/// Gives up on the substream requests that haven't been negotiated within the connect
+	/// timeout, as if libp2p had reported a timeout for them.
+	fn poll_connect_timers(&mut self, cx: &mut Context) {
+		// Nothing is left to time out once the outcome of all the requests is known, including
+		// the ones that the watchdog has given up on.
+		if self.outbound_requests == 0 {
+			self.connect_timers.clear();
+			return;
+		}
+
+		// All the requests have the same connect timeout, so the timers fire in order.
+		loop {
+			let fired = self.connect_timers.front_mut()
+				.map_or(false, |(timer, _)| timer.poll_unpin(cx).is_ready());
+			if !fired {
+				return;
+			}
+			let negotiated = match self.connect_timers.pop_front() {
+				Some((_, negotiated)) => negotiated,
+				None => return,
+			};
+			// Once negotiated, the substream is subject to the handshake timeout instead.
+			if negotiated.swap(true, Ordering::SeqCst) {
+				continue;
+			}
+
+			debug!(
+				target: "sub-libp2p",
+				"📞 Notifications substream on {} not negotiated in time",
+				self.protocol_name,
+			);
+			self.abandoned_requests.push(negotiated);
+			self.handle_dial_upgrade_error(ProtocolsHandlerUpgrErr::Timeout);
+		}
+	}
+
+	/// Forgets about the request of a substream whose outcome libp2p has reported. Returns
+	/// `true` if the request has been given up on, in which case the outcome must be ignored.
+	fn settle_request(&mut self, info: &NotifsOutOpenInfo) -> bool {
+		self.connect_timers.retain(|(_, negotiated)| !Arc::ptr_eq(negotiated, &info.negotiated));
+		let abandoned = self.abandoned_requests.len();
+		self.abandoned_requests.retain(|negotiated| !Arc::ptr_eq(negotiated, &info.negotiated));
+		self.abandoned_requests.len() != abandoned
+	}
+
+	/// Gives up on opening the main substream if libp2p hasn't reported the outcome of the
+	/// request long after its timeout. libp2p should always report either a substream or an
+	/// error, but the handler would otherwise be stuck in the `Opening` state forever.
+	fn poll_open_watchdog(&mut self, cx: &mut Context) {
+		if !matches!(self.state, State::Opening { .. }) || self.reopen_timer.is_some() {
+			return;
+		}
+		let fired = self.open_watchdog.as_mut()
+			.map_or(false, |timer| timer.poll_unpin(cx).is_ready());
+		if !fired {
+			return;
+		}
+		self.open_watchdog = None;
+
+		error!(
+			target: "sub-libp2p",
+			"☎️ No outcome reported for the notifications substream request on {}",
+			self.protocol_name,
+		);
+		// The request is considered as answered, so that a late answer is noticed as such.
+		self.outbound_requests = self.outbound_requests.saturating_sub(1);
+		self.refuse(RefusalCause::WatchdogTimeout);
+	}
+
+	/// Returns whether the handler wants the connection to be kept alive.
+	///
+	/// This is what the handler reports to libp2p through
+	/// [`ProtocolsHandler::connection_keep_alive`], and can be used to find out which handlers
+	/// are keeping a connection open.
+	pub fn keep_alive(&self) -> KeepAlive {
 		match self.state {
 			// We have a small grace period of `INITIAL_KEEPALIVE_TIME` during which we keep the
 			// connection open no matter what, in order to avoid closing and reopening
 			// connections all the time.
-			State::Disabled | State::DisabledOpen(_) | State::DisabledOpening =>
+			State::Disabled | State::DisabledOpen { .. } | State::DisabledOpening =>
 				KeepAlive::Until(self.when_connection_open + INITIAL_KEEPALIVE_TIME),
 			State::Opening { .. } | State::Open { .. } => KeepAlive::Yes,
 			State::Refused | State::Poisoned => KeepAlive::No,
 		}
 	}
 
-	fn poll(
+	/// Removes the next event to send to the outside from the events queues, lifecycle events
+	/// first.
+	fn pop_event(
+		&mut self
+	) -> Option<
+		ProtocolsHandlerEvent<NotificationsOut, NotifsOutOpenInfo, NotifsOutHandlerOut, void::Void>
+	> {
+		if let Some(event) = self.lifecycle_events.pop_front() {
+			return Some(ProtocolsHandlerEvent::Custom(event));
+		}
+		if !matches!(self.state, State::Disabled) {
+			self.disabled_reported = false;
+		} else if !self.disabled_reported {
+			self.disabled_reported = true;
+			let ev = NotifsOutHandlerOut::Disabled { id: self.id };
+			return Some(ProtocolsHandlerEvent::Custom(ev));
+		}
+		match self.events_queue.pop_front() {
+			Some(ProtocolsHandlerEvent::OutboundSubstreamRequest { protocol }) => {
+				let requested_at = Some(self.clock.now());
+				let protocol = protocol.map_info(|info| NotifsOutOpenInfo { requested_at, ..info });
+				Some(ProtocolsHandlerEvent::OutboundSubstreamRequest { protocol })
+			},
+			event => event,
+		}
+	}
+
+	/// Drives the handler forward and returns the next event to report to the outside.
+	fn poll_handler(
 		&mut self,
 		cx: &mut Context,
-	) -> Poll<ProtocolsHandlerEvent<Self::OutboundProtocol, Self::OutboundOpenInfo, Self::OutEvent, Self::Error>> {
-		// Flush the events queue if necessary.
-		if let Some(event) = self.events_queue.pop_front() {
+	) -> Poll<
+		ProtocolsHandlerEvent<NotificationsOut, NotifsOutOpenInfo, NotifsOutHandlerOut, void::Void>
+	> {
+		let mut num = 0;
+		while num < self.retired.len() {
+			if Sink::poll_close(Pin::new(&mut self.retired[num]), cx).is_ready() {
+				self.retired.swap_remove(num);
+			} else {
+				num += 1;
+			}
+		}
+
+		self.poll_connect_timers(cx);
+		self.poll_open_watchdog(cx);
+
+		// Flush the events queues if necessary.
+		if let Some(event) = self.pop_event() {
 			return Poll::Ready(event)
 		}
 
-		match &mut self.state {
-			State::Open { substream, initial_message, close_waker } =>
-				match Sink::poll_flush(Pin::new(substream), cx) {
-					Poll::Pending | Poll::Ready(Ok(())) => {},
-					Poll::Ready(Err(_)) => {
-						if let Some(close_waker) = close_waker.take() {
-							close_waker.wake();
-						}
+		if let Some(timer) = &mut self.reopen_timer {
+			if timer.poll_unpin(cx).is_ready() {
+				self.reopen_timer = None;
+				if let State::Opening { initial_message } = &self.state {
+					let initial_message = initial_message.clone();
+					self.last_reopen = Some(self.clock.now());
+					self.request_substream(initial_message);
+				}
+			}
+		}
 
-						// We try to re-open a substream.
-						let initial_message = mem::replace(initial_message, Vec::new());
-						self.state = State::Opening { initial_message: initial_message.clone() };
-						let proto = NotificationsOut::new(self.protocol_name.clone(), initial_message);
-						self.events_queue.push_back(ProtocolsHandlerEvent::OutboundSubstreamRequest {
-							protocol: SubstreamProtocol::new(proto, ()).with_timeout(OPEN_TIMEOUT),
-						});
-						return Poll::Ready(ProtocolsHandlerEvent::Custom(NotifsOutHandlerOut::Closed));
+		if let State::Open { substream, .. } = &mut self.state {
+			if let Poll::Ready(Err(_)) = Sink::poll_flush(Pin::new(substream), cx) {
+				if let State::Open { initial_message, close_waker, .. } =
+					mem::replace(&mut self.state, State::Poisoned)
+				{
+					if let Some(close_waker) = close_waker {
+						close_waker.wake();
 					}
-				},
-
-			State::DisabledOpen(sub) => match Sink::poll_close(Pin::new(sub), cx) {
-				Poll::Pending => {},
-				Poll::Ready(Ok(())) | Poll::Ready(Err(_)) => {
-					self.state = State::Disabled;
-					return Poll::Ready(ProtocolsHandlerEvent::Custom(NotifsOutHandlerOut::Closed));
-				},
-			},
 
-			_ => {}
+					// We try to re-open a substream.
+					self.reopen_after_error(initial_message);
+				}
+				let reason = CloseReason::Error;
+				let ev = NotifsOutHandlerOut::Closed { id: self.id, reason };
+				return Poll::Ready(ProtocolsHandlerEvent::Custom(ev));
+			}
+		}
+
+		if let State::DisabledOpen { substream } = &mut self.state {
+			if Sink::poll_close(Pin::new(substream), cx).is_ready() {
+				self.state = State::Disabled;
+				let ev = NotifsOutHandlerOut::Closed { id: self.id, reason: CloseReason::Disabled };
+				return Poll::Ready(ProtocolsHandlerEvent::Custom(ev));
+			}
 		}
 
 		Poll::Pending
 	}
 }
 
+/// Closes a substream that is no longer needed, without waiting for the remote.
+fn close_now(mut substream: NotificationsOutSubstream<NegotiatedSubstream>) {
+	if substream.close().now_or_never().is_none() {
+		warn!(target: "sub-libp2p", "📞 Improperly closed outbound notifications substream");
+	}
+}
+
+impl ProtocolsHandler for NotifsOutHandler {
+	type InEvent = NotifsOutHandlerIn;
+	type OutEvent = NotifsOutHandlerOut;
+	type Error = void::Void;
+	type InboundProtocol = DeniedUpgrade;
+	type OutboundProtocol = NotificationsOut;
+	type OutboundOpenInfo = NotifsOutOpenInfo;
+	type InboundOpenInfo = ();
+
+	fn listen_protocol(&self) -> SubstreamProtocol<Self::InboundProtocol, ()> {
+		SubstreamProtocol::new(DeniedUpgrade, ())
+	}
+
+	fn inject_fully_negotiated_inbound(
+		&mut self,
+		proto: <Self::InboundProtocol as InboundUpgrade<NegotiatedSubstream>>::Output,
+		(): ()
+	) {
+		// We should never reach here. `proto` is a `Void`.
+		void::unreachable(proto)
+	}
+
+	fn inject_fully_negotiated_outbound(
+		&mut self,
+		(handshake_msg, substream): <Self::OutboundProtocol as OutboundUpgrade<NegotiatedSubstream>>::Output,
+		info: NotifsOutOpenInfo
+	) {
+		// The upgrade of a request that has been given up on always fails.
+		self.settle_request(&info);
+		self.inject_substream(handshake_msg, substream, info.requested_at)
+	}
+
+	fn inject_event(&mut self, message: NotifsOutHandlerIn) {
+		self.handle_event(message)
+	}
+
+	fn inject_dial_upgrade_error(
+		&mut self,
+		info: NotifsOutOpenInfo,
+		err: ProtocolsHandlerUpgrErr<NotificationsHandshakeError>
+	) {
+		if self.settle_request(&info) {
+			return;
+		}
+		self.handle_dial_upgrade_error(err)
+	}
+
+	fn connection_keep_alive(&self) -> KeepAlive {
+		self.keep_alive()
+	}
+
+	fn poll(
+		&mut self,
+		cx: &mut Context,
+	) -> Poll<ProtocolsHandlerEvent<Self::OutboundProtocol, Self::OutboundOpenInfo, Self::OutEvent, Self::Error>> {
+		self.poll_handler(cx)
+	}
+}
+
 impl fmt::Debug for NotifsOutHandler {
 	fn fmt(&self, f: &mut fmt::Formatter) -> Result<(), fmt::Error> {
 		f.debug_struct("NotifsOutHandler")
-			.field("open", &self.is_open())
+			.field("peer_id", &self.peer_id)
+			.field("protocol", &self.protocol_name)
+			.field("state", &self.state.name())
+			.field("enabled", &self.is_enabled())
+			.field("queued_events", &(self.lifecycle_events.len() + self.events_queue.len()))
+			.field("reopens", &self.reopens)
 			.finish()
 	}
 }
+
+#[cfg(test)]
+mod tests {
+	use super::{
+		Clock, ClockDelay, CloseReason, ConnectionUniqueId, DropReason,
+		NotifsOutHandler, NotifsOutHandlerIn, NotifsOutHandlerOut, NotifsOutHandlerProto,
+		NotifsOutOpenInfo, RefusalCause, State, CONNECT_TIMEOUT,
+		HANDSHAKE_TIMEOUT, INITIAL_KEEPALIVE_TIME, MIN_REOPEN_INTERVAL, OPEN_WATCHDOG_SLACK,
+	};
+	use super::super::memory::Connection;
+	use crate::protocol::generic_proto::upgrade::{
+		NotificationsHandshakeError, NotificationsIn, NotificationsInSubstream, NotificationsOut,
+		NotificationsOutSubstream,
+	};
+	use futures::{prelude::*, task::noop_waker_ref};
+	use libp2p::core::{InboundUpgrade, OutboundUpgrade, UpgradeInfo, upgrade::UpgradeError};
+	use libp2p::swarm::{
+		KeepAlive, NegotiatedSubstream, ProtocolsHandler, ProtocolsHandlerEvent,
+		ProtocolsHandlerUpgrErr, SubstreamProtocol,
+	};
+	use parking_lot::Mutex;
+	use quickcheck::{Arbitrary, Gen, QuickCheck};
+	use rand::Rng;
+	use std::{
+		borrow::Cow, pin::Pin, sync::{Arc, atomic::{AtomicBool, Ordering}},
+		task::{Context, Poll, Waker}, time::Duration,
+	};
+	use wasm_timer::Instant;
+
+	const PROTOCOL: &str = "/test/proto/1";
+
+	/// Remote side of a notifications substream opened by the handler.
+	struct Remote {
+		/// Initial message sent by the handler when opening the substream.
+		initial_message: Vec<u8>,
+		/// The substream itself, until the handler closes it.
+		substream: Option<NotificationsInSubstream<NegotiatedSubstream>>,
+	}
+
+	impl Remote {
+		/// Returns the notifications that have arrived since the last call. Forgets about the
+		/// substream if the handler has closed it.
+		fn received(&mut self) -> Vec<Vec<u8>> {
+			let mut received = Vec::new();
+			while let Some(substream) = &mut self.substream {
+				match substream.poll_next_unpin(&mut Context::from_waker(noop_waker_ref())) {
+					Poll::Ready(Some(Ok(notification))) => received.push(notification.to_vec()),
+					Poll::Ready(Some(Err(err))) => panic!("Inbound substream failed: {}", err),
+					Poll::Ready(None) => self.substream = None,
+					Poll::Pending => break,
+				}
+			}
+			received
+		}
+
+		/// Returns true if the handler has closed the substream.
+		fn is_closed(&mut self) -> bool {
+			self.received();
+			self.substream.is_none()
+		}
+	}
+
+	/// Negotiates `upgrade` on a new substream of `connection`, with a remote that answers with
+	/// `handshake`.
+	fn negotiate(
+		connection: &Connection,
+		upgrade: NotificationsOut,
+		handshake: &[u8],
+	) -> ((Vec<u8>, NotificationsOutSubstream<NegotiatedSubstream>), Remote) {
+		let name = upgrade.protocol_info().next().unwrap();
+		let (dialer, listener) = connection.substream(&name);
+
+		let handshake = handshake.to_vec();
+		let inbound_name = name.clone();
+		let inbound = async move {
+			let (initial_message, mut substream) = NotificationsIn::new(PROTOCOL)
+				.upgrade_inbound(listener, inbound_name)
+				.await
+				.unwrap();
+			substream.send_handshake(handshake);
+			// Writes the handshake, which never has to wait with an in-memory connection.
+			let mut cx = Context::from_waker(noop_waker_ref());
+			let _ = Pin::new(&mut substream).poll_process(&mut cx);
+			Remote { initial_message, substream: Some(substream) }
+		};
+		let outbound = upgrade.upgrade_outbound(dialer, name);
+
+		let (outbound, remote) = futures::executor::block_on(future::join(outbound, inbound));
+		(outbound.unwrap(), remote)
+	}
+
+	/// Negotiates the substream that the handler has requested with `protocol` on `connection`,
+	/// and reports it to the handler.
+	fn answer(
+		handler: &mut NotifsOutHandler,
+		connection: &Connection,
+		protocol: SubstreamProtocol<NotificationsOut, NotifsOutOpenInfo>,
+	) -> Remote {
+		let (_, upgrade, info) = protocol.into_upgrade();
+		let (output, remote) = negotiate(connection, upgrade, b"");
+		handler.inject_fully_negotiated_outbound(output, info);
+		remote
+	}
+
+	fn poll(
+		handler: &mut NotifsOutHandler
+	) -> Poll<
+		ProtocolsHandlerEvent<NotificationsOut, NotifsOutOpenInfo, NotifsOutHandlerOut, void::Void>
+	> {
+		handler.poll_handler(&mut Context::from_waker(noop_waker_ref()))
+	}
+
+	/// Returns the substream request that the handler emits when polled.
+	fn requested(
+		handler: &mut NotifsOutHandler
+	) -> SubstreamProtocol<NotificationsOut, NotifsOutOpenInfo> {
+		match poll(handler) {
+			Poll::Ready(ProtocolsHandlerEvent::OutboundSubstreamRequest { protocol }) => protocol,
+			_ => panic!("expected a substream request"),
+		}
+	}
+
+	/// Waker that records whether it has been woken up.
+	struct WakeFlag(AtomicBool);
+
+	impl futures::task::ArcWake for WakeFlag {
+		fn wake_by_ref(flag: &Arc<Self>) {
+			flag.0.store(true, Ordering::SeqCst);
+		}
+	}
+
+	/// Enables the handler and opens a substream on `connection`.
+	fn open(handler: &mut NotifsOutHandler, connection: &Connection) -> Remote {
+		handler.handle_event(NotifsOutHandlerIn::Enable { initial_message: b"hello".to_vec() });
+		let protocol = requested(handler);
+		let remote = answer(handler, connection, protocol);
+		assert!(matches!(
+			poll(handler),
+			Poll::Ready(ProtocolsHandlerEvent::Custom(NotifsOutHandlerOut::Open { .. }))
+		));
+		remote
+	}
+
+	#[test]
+	fn reenable_while_closing_opens_new_substream() {
+		let mut handler = NotifsOutHandlerProto::new(PROTOCOL).build();
+		let connection = Connection::new();
+		let mut first = open(&mut handler, &connection);
+
+		// Disabling the handler starts closing the substream, which only progresses when the
+		// handler is polled.
+		handler.handle_event(NotifsOutHandlerIn::Disable);
+		assert!(!first.is_closed());
+
+		// Enabling the handler again must not reuse the substream being closed.
+		let mut second = open(&mut handler, &connection);
+
+		handler.send_or_discard(b"notif".to_vec());
+		assert!(poll(&mut handler).is_pending());
+		assert!(first.received().is_empty());
+		assert!(first.is_closed());
+		assert_eq!(second.received(), vec![b"notif".to_vec()]);
+	}
+
+	#[test]
+	fn open_timeout_is_reported() {
+		let mut handler = NotifsOutHandlerProto::new("/test/proto/1").build();
+		handler.handle_event(NotifsOutHandlerIn::Enable { initial_message: Vec::new() });
+		assert!(matches!(
+			poll(&mut handler),
+			Poll::Ready(ProtocolsHandlerEvent::OutboundSubstreamRequest { .. })
+		));
+
+		handler.handle_dial_upgrade_error(ProtocolsHandlerUpgrErr::Timeout);
+		assert!(matches!(
+			poll(&mut handler),
+			Poll::Ready(ProtocolsHandlerEvent::Custom(NotifsOutHandlerOut::Refused {
+				cause: RefusalCause::Timeout,
+				..
+			}))
+		));
+	}
+
+	#[test]
+	fn invalid_protocol_names_are_rejected() {
+		assert!(NotifsOutHandlerProto::try_new("").is_err());
+		assert!(NotifsOutHandlerProto::try_new("test/proto/1").is_err());
+		assert!(NotifsOutHandlerProto::try_new("/test/proto/1").is_ok());
+	}
+
+	#[test]
+	fn send_or_discard_reports_notifications_sent_while_closed() {
+		let mut handler = NotifsOutHandlerProto::new("/test/proto/1").build();
+		handler.send_or_discard(b"notif".to_vec());
+		assert!(matches!(
+			poll(&mut handler),
+			Poll::Ready(ProtocolsHandlerEvent::Custom(NotifsOutHandlerOut::SendDropped {
+				reason: DropReason::NotOpen,
+				..
+			}))
+		));
+	}
+
+	#[test]
+	fn refusal_is_reported_before_queued_events() {
+		let mut handler = NotifsOutHandlerProto::new("/test/proto/1").build();
+		handler.handle_event(NotifsOutHandlerIn::Enable { initial_message: Vec::new() });
+		assert!(matches!(
+			poll(&mut handler),
+			Poll::Ready(ProtocolsHandlerEvent::OutboundSubstreamRequest { .. })
+		));
+
+		// An event is queued while the substream is being opened, then the remote refuses it.
+		handler.send_or_discard(b"notif".to_vec());
+		handler.handle_dial_upgrade_error(ProtocolsHandlerUpgrErr::Timeout);
+		assert!(matches!(
+			poll(&mut handler),
+			Poll::Ready(ProtocolsHandlerEvent::Custom(NotifsOutHandlerOut::Refused { .. }))
+		));
+		assert!(matches!(
+			poll(&mut handler),
+			Poll::Ready(ProtocolsHandlerEvent::Custom(NotifsOutHandlerOut::SendDropped { .. }))
+		));
+	}
+
+	#[test]
+	fn late_substream_is_closed_unused() {
+		let mut handler = NotifsOutHandlerProto::new(PROTOCOL).build();
+		handler.handle_event(NotifsOutHandlerIn::Enable { initial_message: Vec::new() });
+		let protocol = requested(&mut handler);
+
+		// The handler is disabled before the substream has been negotiated.
+		handler.handle_event(NotifsOutHandlerIn::Disable);
+		let connection = Connection::new();
+		let mut remote = answer(&mut handler, &connection, protocol);
+		assert!(!handler.is_open());
+		assert!(matches!(
+			poll(&mut handler),
+			Poll::Ready(ProtocolsHandlerEvent::Custom(NotifsOutHandlerOut::Disabled { .. }))
+		));
+		assert!(poll(&mut handler).is_pending());
+		assert!(remote.is_closed());
+	}
+
+	#[test]
+	fn rapid_failures_delay_reopening() {
+		let mut handler = NotifsOutHandlerProto::new(PROTOCOL).build();
+		let connection = Connection::new();
+		open(&mut handler, &connection);
+
+		// The first failure re-opens the substream immediately.
+		connection.close();
+		assert!(matches!(
+			poll(&mut handler),
+			Poll::Ready(ProtocolsHandlerEvent::Custom(NotifsOutHandlerOut::Closed {
+				reason: CloseReason::Error,
+				..
+			}))
+		));
+		let protocol = requested(&mut handler);
+
+		// The new substream fails right away, and re-opening it is delayed.
+		let connection = Connection::new();
+		answer(&mut handler, &connection, protocol);
+		connection.close();
+		assert!(matches!(
+			poll(&mut handler),
+			Poll::Ready(ProtocolsHandlerEvent::Custom(NotifsOutHandlerOut::Open { .. }))
+		));
+		assert!(matches!(
+			poll(&mut handler),
+			Poll::Ready(ProtocolsHandlerEvent::Custom(NotifsOutHandlerOut::Closed {
+				reason: CloseReason::Error,
+				..
+			}))
+		));
+		assert!(poll(&mut handler).is_pending());
+	}
+
+	#[test]
+	fn handshake_timeout_is_told_apart() {
+		let mut handler = NotifsOutHandlerProto::new("/test/proto/1").build();
+		handler.handle_event(NotifsOutHandlerIn::Enable { initial_message: Vec::new() });
+		assert!(matches!(
+			poll(&mut handler),
+			Poll::Ready(ProtocolsHandlerEvent::OutboundSubstreamRequest { .. })
+		));
+
+		handler.handle_dial_upgrade_error(ProtocolsHandlerUpgrErr::Upgrade(
+			UpgradeError::Apply(NotificationsHandshakeError::Timeout)
+		));
+		assert!(matches!(
+			poll(&mut handler),
+			Poll::Ready(ProtocolsHandlerEvent::Custom(NotifsOutHandlerOut::Refused {
+				cause: RefusalCause::HandshakeTimeout,
+				..
+			}))
+		));
+	}
+
+	#[test]
+	fn disabled_is_reported_once_after_closing() {
+		let mut handler = NotifsOutHandlerProto::new(PROTOCOL).build();
+		assert!(poll(&mut handler).is_pending());
+
+		open(&mut handler, &Connection::new());
+		handler.handle_event(NotifsOutHandlerIn::Disable);
+		assert!(matches!(
+			poll(&mut handler),
+			Poll::Ready(ProtocolsHandlerEvent::Custom(NotifsOutHandlerOut::Closed { .. }))
+		));
+		assert!(matches!(
+			poll(&mut handler),
+			Poll::Ready(ProtocolsHandlerEvent::Custom(NotifsOutHandlerOut::Disabled { .. }))
+		));
+		assert!(poll(&mut handler).is_pending());
+
+		// Disabling a handler that is already disabled doesn't report anything.
+		handler.handle_event(NotifsOutHandlerIn::Disable);
+		assert!(poll(&mut handler).is_pending());
+	}
+
+	#[test]
+	fn debug_output_describes_handler() {
+		let mut handler = NotifsOutHandlerProto::new(PROTOCOL).build();
+		open(&mut handler, &Connection::new());
+
+		let debug = format!("{:?}", handler);
+		assert!(debug.contains("\"/test/proto/1\""));
+		assert!(debug.contains("state: \"Open\""));
+		assert!(debug.contains("enabled: true"));
+		assert!(!debug.contains('\n'));
+	}
+
+	/// Clock whose time only moves forward when the test says so.
+	struct MockClock(Arc<Mutex<MockTime>>);
+
+	struct MockTime {
+		/// Current time of the clock.
+		now: Instant,
+		/// Tasks waiting for a timer of the clock to fire.
+		wakers: Vec<Waker>,
+	}
+
+	impl MockClock {
+		fn new(now: Instant) -> Arc<Self> {
+			Arc::new(MockClock(Arc::new(Mutex::new(MockTime { now, wakers: Vec::new() }))))
+		}
+
+		fn advance(&self, duration: Duration) {
+			let mut time = self.0.lock();
+			time.now += duration;
+			for waker in time.wakers.drain(..) {
+				waker.wake();
+			}
+		}
+	}
+
+	impl Clock for MockClock {
+		fn now(&self) -> Instant {
+			self.0.lock().now
+		}
+
+		fn delay(&self, duration: Duration) -> ClockDelay {
+			let time = self.0.clone();
+			let deadline = self.now() + duration;
+			Box::pin(future::poll_fn(move |cx| {
+				let mut time = time.lock();
+				if time.now >= deadline {
+					Poll::Ready(())
+				} else {
+					time.wakers.push(cx.waker().clone());
+					Poll::Pending
+				}
+			}))
+		}
+	}
+
+	#[test]
+	fn time_is_read_from_the_injected_clock() {
+		let start = Instant::now();
+		let clock = MockClock::new(start);
+		let handler = NotifsOutHandlerProto::new("/test/proto/1")
+			.with_clock(clock.clone())
+			.build();
+		assert_eq!(handler.keep_alive(), KeepAlive::Until(start + INITIAL_KEEPALIVE_TIME));
+	}
+
+	/// Input that can be given to a handler, for the purpose of checking its state transitions.
+	#[derive(Debug, Clone, Copy)]
+	enum Input {
+		Enable,
+		Disable,
+		Send,
+		Negotiated,
+		DialError,
+		Poll,
+	}
+
+	impl Arbitrary for Input {
+		fn arbitrary<G: Gen>(g: &mut G) -> Self {
+			match g.gen_range(0, 6) {
+				0 => Input::Enable,
+				1 => Input::Disable,
+				2 => Input::Send,
+				3 => Input::Negotiated,
+				4 => Input::DialError,
+				_ => Input::Poll,
+			}
+		}
+	}
+
+	/// Returns the state, as named by `State::name`, that a handler in `state` is expected to
+	/// switch to after `input`, assuming that its substreams never fail.
+	///
+	/// `Negotiated` and `DialError` are only legal if the handler has requested a substream that
+	/// hasn't been answered yet. `Poll` polls the handler until it has nothing left to do.
+	fn next_state(state: &'static str, input: Input) -> &'static str {
+		match (state, input) {
+			(_, Input::Send) => state,
+
+			("Disabled", Input::Enable) |
+			("DisabledOpen", Input::Enable) |
+			("DisabledOpening", Input::Enable) => "Opening",
+			(_, Input::Enable) => state,
+
+			("Opening", Input::Disable) => "DisabledOpening",
+			("Refused", Input::Disable) => "Disabled",
+			("Open", Input::Disable) => "DisabledOpen",
+			(_, Input::Disable) => state,
+
+			("Opening", Input::Negotiated) => "Open",
+			("Opening", Input::DialError) => "Refused",
+			("DisabledOpening", Input::Negotiated) |
+			("DisabledOpening", Input::DialError) => "Disabled",
+			(_, Input::Negotiated) | (_, Input::DialError) => state,
+
+			("DisabledOpen", Input::Poll) => "Disabled",
+			(_, Input::Poll) => state,
+		}
+	}
+
+	#[test]
+	fn random_inputs_follow_transition_table() {
+		fn property(inputs: Vec<Input>) {
+			let mut handler = NotifsOutHandlerProto::new(PROTOCOL)
+				.build();
+			let connection = Connection::new();
+			// Substreams requested by the handler that haven't been answered yet.
+			let mut requested = Vec::new();
+
+			for input in inputs {
+				let before = handler.state.name();
+				match input {
+					Input::Enable => handler.handle_event(NotifsOutHandlerIn::Enable {
+						initial_message: b"hello".to_vec(),
+					}),
+					Input::Disable => handler.handle_event(NotifsOutHandlerIn::Disable),
+					Input::Send => handler.send_or_discard(b"hi".to_vec()),
+					Input::Negotiated | Input::DialError if requested.is_empty() => continue,
+					Input::Negotiated => {
+						answer(&mut handler, &connection, requested.remove(0));
+					},
+					Input::DialError => {
+						let (_, _, info) = requested.remove(0).into_upgrade();
+						handler.inject_dial_upgrade_error(info, ProtocolsHandlerUpgrErr::Timeout);
+					},
+					Input::Poll => {
+						while let Poll::Ready(event) = poll(&mut handler) {
+							if let ProtocolsHandlerEvent::OutboundSubstreamRequest { protocol } =
+								event
+							{
+								requested.push(protocol);
+							}
+						}
+					},
+				}
+
+				let after = handler.state.name();
+				assert_ne!(after, "Poisoned");
+				assert_eq!(after, next_state(before, input), "{:?} in state {}", input, before);
+			}
+		}
+
+		QuickCheck::new().quickcheck(property as fn(Vec<Input>))
+	}
+
+	#[test]
+	fn open_rtt_is_measured() {
+		let clock = MockClock::new(Instant::now());
+		let mut handler = NotifsOutHandlerProto::new("/test/proto/1")
+			.with_clock(clock.clone())
+			.build();
+		handler.handle_event(NotifsOutHandlerIn::Enable { initial_message: b"hello".to_vec() });
+
+		// The time spent before the request is reported to libp2p isn't counted.
+		clock.advance(Duration::from_secs(1));
+		let protocol = requested(&mut handler);
+		clock.advance(Duration::from_millis(30));
+		answer(&mut handler, &Connection::new(), protocol);
+		match poll(&mut handler) {
+			Poll::Ready(ProtocolsHandlerEvent::Custom(NotifsOutHandlerOut::Open { rtt, .. })) =>
+				assert_eq!(rtt, Some(Duration::from_millis(30))),
+			_ => panic!("expected the substream to be open"),
+		}
+	}
+
+	#[test]
+	fn handlers_of_a_connection_share_its_id() {
+		let connection_id = ConnectionUniqueId::allocate();
+		let event_id = |mut handler: NotifsOutHandler| {
+			// Notifications sent while disabled are dropped, which gives us an event to look at.
+			handler.send_or_discard(Vec::new());
+			match poll(&mut handler) {
+				Poll::Ready(ProtocolsHandlerEvent::Custom(NotifsOutHandlerOut::SendDropped {
+					id, ..
+				})) => id,
+				_ => panic!("expected the notification to be dropped"),
+			}
+		};
+
+		let first = NotifsOutHandlerProto::new("/test/proto/1")
+			.with_connection_id(connection_id)
+			.build();
+		let second = NotifsOutHandlerProto::new("/test/proto/2")
+			.with_connection_id(connection_id)
+			.build();
+		assert_eq!(event_id(first), connection_id);
+		assert_eq!(event_id(second), connection_id);
+
+		// Handlers created without an identifier are each given a new one.
+		let other = event_id(NotifsOutHandlerProto::new("/test/proto/1").build());
+		assert_ne!(other, connection_id);
+		assert_ne!(other, event_id(NotifsOutHandlerProto::new("/test/proto/1").build()));
+	}
+
+	#[test]
+	fn queued_events_can_be_peeked() {
+		let mut handler = NotifsOutHandlerProto::new("/test/proto/1")
+			.build();
+		handler.handle_event(NotifsOutHandlerIn::Enable { initial_message: Vec::new() });
+		handler.send_or_discard(Vec::new());
+		assert_eq!(handler.peek_events(), vec!["OutboundSubstreamRequest", "SendDropped"]);
+
+		// Peeking doesn't remove anything.
+		assert_eq!(handler.peek_events().len(), 2);
+		assert!(matches!(
+			poll(&mut handler),
+			Poll::Ready(ProtocolsHandlerEvent::OutboundSubstreamRequest { .. })
+		));
+		assert_eq!(handler.peek_events(), vec!["SendDropped"]);
+	}
+
+	/// Drives a [`NotifsOutHandler`] through its `ProtocolsHandler` implementation only, the way
+	/// libp2p does, with a remote that answers all the substream requests.
+	mod conformance {
+		use super::super::{NotifsOutHandler, NotifsOutHandlerIn, NotifsOutHandlerOut};
+		use super::super::{NotifsOutHandlerProto, NotifsOutOpenInfo};
+		use super::{Connection, PROTOCOL, Remote, negotiate};
+		use crate::protocol::generic_proto::upgrade::NotificationsOut;
+		use futures::task::noop_waker_ref;
+		use libp2p::core::{ConnectedPoint, PeerId};
+		use libp2p::swarm::{
+			IntoProtocolsHandler, ProtocolsHandler, ProtocolsHandlerEvent, SubstreamProtocol,
+		};
+		use std::task::{Context, Poll};
+
+		/// Outbound handler whose substream requests are answered by the remote of an in-memory
+		/// connection.
+		struct Harness {
+			handler: NotifsOutHandler,
+			connection: Connection,
+			/// Handshake sent back by the remote.
+			handshake: Vec<u8>,
+			/// Remote sides of the substreams that have been opened, oldest first.
+			remotes: Vec<Remote>,
+		}
+
+		impl Harness {
+			fn new(proto: NotifsOutHandlerProto, handshake: &[u8]) -> Self {
+				Harness {
+					handler: proto.into_handler(&PeerId::random(), &ConnectedPoint::Dialer {
+						address: "/memory/1".parse().unwrap(),
+					}),
+					connection: Connection::new(),
+					handshake: handshake.to_vec(),
+					remotes: Vec::new(),
+				}
+			}
+
+			/// Polls the handler until it has nothing left to do, opening the substreams it
+			/// requests, and returns the events it has emitted.
+			fn drive(&mut self) -> Vec<NotifsOutHandlerOut> {
+				let mut events = Vec::new();
+				loop {
+					let mut cx = Context::from_waker(noop_waker_ref());
+					match self.handler.poll(&mut cx) {
+						Poll::Ready(ProtocolsHandlerEvent::OutboundSubstreamRequest { protocol }) =>
+							self.negotiate(protocol),
+						Poll::Ready(ProtocolsHandlerEvent::Custom(ev)) => events.push(ev),
+						Poll::Ready(ProtocolsHandlerEvent::Close(err)) => void::unreachable(err),
+						Poll::Pending => return events,
+					}
+				}
+			}
+
+			/// Negotiates the requested substream with the remote, and hands it to the handler.
+			fn negotiate(&mut self, protocol: SubstreamProtocol<NotificationsOut, NotifsOutOpenInfo>) {
+				let (_, upgrade, info) = protocol.into_upgrade();
+				let (output, remote) = negotiate(&self.connection, upgrade, &self.handshake);
+				self.remotes.push(remote);
+				self.handler.inject_fully_negotiated_outbound(output, info);
+			}
+
+			/// Returns the initial messages received by the remote, one per substream.
+			fn initial_messages(&self) -> Vec<Vec<u8>> {
+				self.remotes.iter().map(|remote| remote.initial_message.clone()).collect()
+			}
+
+			/// Returns the notifications that have arrived on the substream opened last.
+			fn received(&mut self) -> Vec<Vec<u8>> {
+				self.remotes.last_mut().map_or_else(Vec::new, Remote::received)
+			}
+		}
+
+		#[test]
+		fn notifications_arrive_intact() {
+			let mut harness = Harness::new(NotifsOutHandlerProto::new(PROTOCOL), b"welcome");
+			harness.handler.handle_event(NotifsOutHandlerIn::Enable {
+				initial_message: b"hello".to_vec(),
+			});
+			let events = harness.drive();
+			assert!(events.iter().any(|ev| matches!(ev,
+				NotifsOutHandlerOut::Open { handshake, .. } if handshake == b"welcome"
+			)));
+			assert_eq!(harness.initial_messages(), vec![b"hello".to_vec()]);
+
+			let notifications = vec![b"first".to_vec(), Vec::new(), vec![0xab; 70_000]];
+			for notification in &notifications {
+				harness.handler.send_or_discard(notification.clone());
+			}
+			harness.drive();
+			assert_eq!(harness.received(), notifications);
+		}
+
+		#[test]
+		fn substream_is_closed_and_reopened() {
+			let mut harness = Harness::new(NotifsOutHandlerProto::new(PROTOCOL), b"welcome");
+			harness.handler.handle_event(NotifsOutHandlerIn::Enable {
+				initial_message: b"hello".to_vec(),
+			});
+			harness.drive();
+			harness.handler.send_or_discard(b"before".to_vec());
+			harness.drive();
+
+			harness.handler.handle_event(NotifsOutHandlerIn::Disable);
+			harness.drive();
+			assert_eq!(harness.received(), vec![b"before".to_vec()]);
+			assert!(harness.remotes[0].is_closed());
+			assert!(!harness.handler.is_open());
+
+			harness.handler.handle_event(NotifsOutHandlerIn::Enable {
+				initial_message: b"hello again".to_vec(),
+			});
+			let events = harness.drive();
+			assert!(events.iter().any(|ev| matches!(ev, NotifsOutHandlerOut::Open { .. })));
+			let initial_messages = vec![b"hello".to_vec(), b"hello again".to_vec()];
+			assert_eq!(harness.initial_messages(), initial_messages);
+
+			harness.handler.send_or_discard(b"after".to_vec());
+			harness.drive();
+			assert_eq!(harness.received(), vec![b"after".to_vec()]);
+		}
+	}
+
+	#[test]
+	fn watchdog_gives_up_on_unanswered_requests() {
+		let clock = MockClock::new(Instant::now());
+		let mut handler = NotifsOutHandlerProto::new("/test/proto/1")
+			.with_clock(clock.clone())
+			.build();
+		handler.handle_event(NotifsOutHandlerIn::Enable { initial_message: Vec::new() });
+		match poll(&mut handler) {
+			Poll::Ready(ProtocolsHandlerEvent::OutboundSubstreamRequest { protocol }) =>
+				protocol.info().negotiated.store(true, Ordering::SeqCst),
+			_ => panic!("expected a substream request"),
+		}
+
+		// The substream is negotiated, but neither a substream nor an error is ever reported
+		// for the request.
+		clock.advance(CONNECT_TIMEOUT + HANDSHAKE_TIMEOUT);
+		assert!(poll(&mut handler).is_pending());
+		assert!(matches!(handler.state, State::Opening { .. }));
+
+		// The task is woken up once the watchdog fires.
+		let woken = Arc::new(WakeFlag(AtomicBool::new(false)));
+		let waker = futures::task::waker(woken.clone());
+		assert!(handler.poll_handler(&mut Context::from_waker(&waker)).is_pending());
+		clock.advance(OPEN_WATCHDOG_SLACK);
+		assert!(woken.0.load(Ordering::SeqCst));
+		assert!(matches!(
+			poll(&mut handler),
+			Poll::Ready(ProtocolsHandlerEvent::Custom(NotifsOutHandlerOut::Refused {
+				cause: RefusalCause::WatchdogTimeout,
+				..
+			}))
+		));
+		assert!(matches!(handler.state, State::Refused));
+	}
+
+	#[test]
+	fn connect_timeout_is_enforced_on_its_own() {
+		let clock = MockClock::new(Instant::now());
+		let mut handler = NotifsOutHandlerProto::new("/test/proto/1")
+			.with_clock(clock.clone())
+			.build();
+		handler.handle_event(NotifsOutHandlerIn::Enable { initial_message: Vec::new() });
+		let (proto, info) = match poll(&mut handler) {
+			Poll::Ready(ProtocolsHandlerEvent::OutboundSubstreamRequest { protocol }) =>
+				(protocol.upgrade().clone(), protocol.info().clone()),
+			_ => panic!("expected a substream request"),
+		};
+
+		// The substream isn't negotiated in time, regardless of the handshake timeout.
+		clock.advance(CONNECT_TIMEOUT);
+		assert!(matches!(
+			poll(&mut handler),
+			Poll::Ready(ProtocolsHandlerEvent::Custom(NotifsOutHandlerOut::Refused {
+				cause: RefusalCause::Timeout,
+				..
+			}))
+		));
+
+		// Once negotiated late, the substream isn't used, and its failure is ignored.
+		let name = Cow::Borrowed(&b"/test/proto/1"[..]);
+		let (substream, _) = Connection::new().substream(&name);
+		let upgrade = proto.upgrade_outbound(substream, name);
+		let err = match futures::executor::block_on(upgrade) {
+			Err(err) => err,
+			Ok(_) => panic!("the substream has been given up on"),
+		};
+		assert!(matches!(err, NotificationsHandshakeError::Abandoned));
+		handler.inject_dial_upgrade_error(
+			info,
+			ProtocolsHandlerUpgrErr::Upgrade(UpgradeError::Apply(err))
+		);
+		assert!(poll(&mut handler).is_pending());
+	}
+
+	#[test]
+	fn disabling_cancels_delayed_reopening() {
+		let clock = MockClock::new(Instant::now());
+		let mut handler = NotifsOutHandlerProto::new("/test/proto/1")
+			.with_clock(clock.clone())
+			.build();
+		let connection = Connection::new();
+		open(&mut handler, &connection);
+
+		// Two failures in a row arm the timer delaying the re-opening.
+		connection.close();
+		let protocol = loop {
+			if let Poll::Ready(ProtocolsHandlerEvent::OutboundSubstreamRequest { protocol }) =
+				poll(&mut handler)
+			{
+				break protocol;
+			}
+		};
+		let connection = Connection::new();
+		answer(&mut handler, &connection, protocol);
+		connection.close();
+		while poll(&mut handler).is_ready() {}
+		assert!(handler.is_enabled());
+
+		handler.handle_event(NotifsOutHandlerIn::Disable);
+		assert!(matches!(handler.state, State::Disabled));
+
+		clock.advance(MIN_REOPEN_INTERVAL * 2);
+		while let Poll::Ready(ev) = poll(&mut handler) {
+			assert!(!matches!(ev, ProtocolsHandlerEvent::OutboundSubstreamRequest { .. }));
+		}
+		assert!(matches!(handler.state, State::Disabled));
+	}
+}
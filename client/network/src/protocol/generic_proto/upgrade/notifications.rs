@@ -35,11 +35,14 @@
 ///
 
 use bytes::BytesMut;
-use futures::prelude::*;
+use futures::{future::{self, Either}, prelude::*};
 use futures_codec::Framed;
+use futures_timer::Delay;
 use libp2p::core::{UpgradeInfo, InboundUpgrade, OutboundUpgrade, upgrade};
 use log::error;
-use std::{borrow::Cow, convert::Infallible, io, iter, mem, pin::Pin, task::{Context, Poll}};
+use std::{borrow::Cow, convert::Infallible, io, iter, mem, pin::Pin, time::Duration};
+use std::sync::{Arc, atomic::{AtomicBool, Ordering}};
+use std::task::{Context, Poll};
 use unsigned_varint::codec::UviBytes;
 
 /// Maximum allowed size of the two handshake messages, in bytes.
@@ -61,6 +64,12 @@ pub struct NotificationsOut {
 	protocol_name: Cow<'static, str>,
 	/// Message to send when we start the handshake.
 	initial_message: Vec<u8>,
+	/// Maximum duration to wait for the handshake of the remote once the substream has been
+	/// negotiated. `None` if unlimited.
+	handshake_timeout: Option<Duration>,
+	/// Flag set once the substream has been negotiated, shared with whoever requested the
+	/// substream, if any.
+	negotiated: Option<Arc<AtomicBool>>,
 }
 
 /// A substream for incoming notification messages.
@@ -299,8 +308,29 @@ impl NotificationsOut {
 		NotificationsOut {
 			protocol_name: protocol_name.into(),
 			initial_message,
+			handshake_timeout: None,
+			negotiated: None,
 		}
 	}
+
+	/// Sets the maximum duration to wait for the handshake of the remote once the substream has
+	/// been negotiated. If the handshake doesn't arrive in time, the upgrade fails with
+	/// [`NotificationsHandshakeError::Timeout`].
+	pub fn with_handshake_timeout(mut self, timeout: Duration) -> Self {
+		self.handshake_timeout = Some(timeout);
+		self
+	}
+
+	/// Sets `flag` once the substream has been negotiated, which lets the requester find out
+	/// whether the negotiation is still going on.
+	///
+	/// The requester can give up on the substream by setting `flag` itself. If the flag is
+	/// already set once the substream has been negotiated, the upgrade fails with
+	/// [`NotificationsHandshakeError::Abandoned`] without sending anything.
+	pub fn with_negotiated_flag(mut self, flag: Arc<AtomicBool>) -> Self {
+		self.negotiated = Some(flag);
+		self
+	}
 }
 
 impl UpgradeInfo for NotificationsOut {
@@ -329,21 +359,40 @@ where TSubstream: AsyncRead + AsyncWrite + Unpin + Send + 'static,
 		_: Self::Info,
 	) -> Self::Future {
 		Box::pin(async move {
+			if let Some(negotiated) = &self.negotiated {
+				if negotiated.swap(true, Ordering::SeqCst) {
+					return Err(NotificationsHandshakeError::Abandoned);
+				}
+			}
+
 			upgrade::write_with_len_prefix(&mut socket, &self.initial_message).await?;
 
 			// Reading handshake.
-			let handshake_len = unsigned_varint::aio::read_usize(&mut socket).await?;
-			if handshake_len > MAX_HANDSHAKE_SIZE {
-				return Err(NotificationsHandshakeError::TooLarge {
-					requested: handshake_len,
-					max: MAX_HANDSHAKE_SIZE,
-				});
-			}
+			let read_handshake = async {
+				let handshake_len = unsigned_varint::aio::read_usize(&mut socket).await?;
+				if handshake_len > MAX_HANDSHAKE_SIZE {
+					return Err(NotificationsHandshakeError::TooLarge {
+						requested: handshake_len,
+						max: MAX_HANDSHAKE_SIZE,
+					});
+				}
 
-			let mut handshake = vec![0u8; handshake_len];
-			if !handshake.is_empty() {
-				socket.read_exact(&mut handshake).await?;
-			}
+				let mut handshake = vec![0u8; handshake_len];
+				if !handshake.is_empty() {
+					socket.read_exact(&mut handshake).await?;
+				}
+				Ok(handshake)
+			};
+
+			let handshake = match self.handshake_timeout {
+				Some(timeout) => {
+					match future::select(Box::pin(read_handshake), Delay::new(timeout)).await {
+						Either::Left((handshake, _)) => handshake?,
+						Either::Right(_) => return Err(NotificationsHandshakeError::Timeout),
+					}
+				},
+				None => read_handshake.await?,
+			};
 
 			Ok((handshake, NotificationsOutSubstream {
 				socket: Framed::new(socket, UviBytes::default()),
@@ -399,6 +448,17 @@ pub enum NotificationsHandshakeError {
 
 	/// Error while decoding the variable-length integer.
 	VarintDecode(unsigned_varint::decode::Error),
+
+	/// The remote didn't send back its handshake within the handshake timeout.
+	#[from(ignore)]
+	#[display(fmt = "Timeout while waiting for the handshake of the remote")]
+	Timeout,
+
+	/// The substream has been given up on before it was negotiated, through the flag passed to
+	/// [`NotificationsOut::with_negotiated_flag`].
+	#[from(ignore)]
+	#[display(fmt = "Substream given up on before being negotiated")]
+	Abandoned,
 }
 
 impl From<unsigned_varint::io::ReadError> for NotificationsHandshakeError {
@@ -423,12 +483,15 @@ pub enum NotificationsOutError {
 
 #[cfg(test)]
 mod tests {
-	use super::{NotificationsIn, NotificationsOut};
+	use super::{NotificationsIn, NotificationsOut, NotificationsHandshakeError};
 
 	use async_std::net::{TcpListener, TcpStream};
 	use futures::{prelude::*, channel::oneshot};
-	use libp2p::core::upgrade;
-	use std::borrow::Cow;
+	use libp2p::core::{OutboundUpgrade, upgrade};
+	use std::{
+		borrow::Cow, io, pin::Pin, sync::{Arc, Mutex, atomic::AtomicBool}, task::{Context, Poll},
+		time::Duration,
+	};
 
 	#[test]
 	fn basic_works() {
@@ -608,4 +671,89 @@ mod tests {
 
 		async_std::task::block_on(client);
 	}
+
+	#[test]
+	fn slow_handshake_times_out() {
+		const PROTO_NAME: Cow<'static, str> = Cow::Borrowed("/test/proto/1");
+		let (listener_addr_tx, listener_addr_rx) = oneshot::channel();
+		let (done_tx, done_rx) = oneshot::channel::<()>();
+
+		let client = async_std::task::spawn(async move {
+			let socket = TcpStream::connect(listener_addr_rx.await.unwrap()).await.unwrap();
+			let ret = upgrade::apply_outbound(
+				socket,
+				NotificationsOut::new(PROTO_NAME, &b"initial message"[..])
+					.with_handshake_timeout(Duration::from_millis(100)),
+				upgrade::Version::V1
+			).await;
+			assert!(matches!(
+				ret,
+				Err(upgrade::UpgradeError::Apply(NotificationsHandshakeError::Timeout))
+			));
+			done_tx.send(()).unwrap();
+		});
+
+		async_std::task::block_on(async move {
+			let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+			listener_addr_tx.send(listener.local_addr().unwrap()).unwrap();
+
+			let (socket, _) = listener.accept().await.unwrap();
+			let (initial_message, _substream) = upgrade::apply_inbound(
+				socket,
+				NotificationsIn::new(PROTO_NAME)
+			).await.unwrap();
+			assert_eq!(initial_message, b"initial message");
+
+			// We never send back our handshake, and keep the substream open until the client
+			// has given up.
+			done_rx.await.unwrap();
+		});
+
+		async_std::task::block_on(client);
+	}
+
+	/// Substream that accepts all the writes and records their sizes.
+	#[derive(Clone, Default)]
+	struct RecordingSubstream(Arc<Mutex<Vec<usize>>>);
+
+	impl AsyncRead for RecordingSubstream {
+		fn poll_read(
+			self: Pin<&mut Self>,
+			_: &mut Context,
+			_: &mut [u8]
+		) -> Poll<io::Result<usize>> {
+			Poll::Pending
+		}
+	}
+
+	impl AsyncWrite for RecordingSubstream {
+		fn poll_write(
+			self: Pin<&mut Self>,
+			_: &mut Context,
+			buf: &[u8]
+		) -> Poll<io::Result<usize>> {
+			self.0.lock().unwrap().push(buf.len());
+			Poll::Ready(Ok(buf.len()))
+		}
+
+		fn poll_flush(self: Pin<&mut Self>, _: &mut Context) -> Poll<io::Result<()>> {
+			Poll::Ready(Ok(()))
+		}
+
+		fn poll_close(self: Pin<&mut Self>, _: &mut Context) -> Poll<io::Result<()>> {
+			Poll::Ready(Ok(()))
+		}
+	}
+
+	#[test]
+	fn abandoned_substream_sends_nothing() {
+		let socket = RecordingSubstream::default();
+		let negotiated = Arc::new(AtomicBool::new(true));
+		let proto = NotificationsOut::new("/test/proto/1", &b"initial message"[..])
+			.with_negotiated_flag(negotiated);
+		let name = Cow::Borrowed(&b"/test/proto/1"[..]);
+		let ret = async_std::task::block_on(proto.upgrade_outbound(socket.clone(), name));
+		assert!(matches!(ret, Err(NotificationsHandshakeError::Abandoned)));
+		assert!(socket.0.lock().unwrap().is_empty());
+	}
 }